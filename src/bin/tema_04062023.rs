@@ -24,7 +24,8 @@
 //presentato ovvero una struttura dati che necessita di fornire a più di un thread un riferimento
 //a se stessa e ognuna dei riferimenti forti (Arc::clone) contiene internamente un puntatore a se
 //stessa, prevenendo cosi' la deallocazione della struttura.
-use std::{rc::{ Rc, Weak }, sync::{Arc, Condvar, Mutex}, thread::spawn};
+use std::{future::poll_fn, rc::{ Rc, Weak }, sync::{Arc, Condvar, Mutex}, task::Poll, thread::spawn};
+use soluzione_temi_malnati::task::Wakers;
 
 fn weak_example() {
     let rc = Rc::new(5);
@@ -56,6 +57,11 @@ fn weak_example() {
 struct BarrierState {
     size: usize,
     arrived: usize,
+    // Incrementata ad ogni apertura della barriera: chi attende si sblocca quando cambia, così la
+    // fase di uscita non si mescola con gli arrivi del ciclo successivo
+    generation: usize,
+    // Task in attesa in wait_async
+    wakers: Wakers,
 }
 
 impl BarrierState {
@@ -63,6 +69,8 @@ impl BarrierState {
         BarrierState {
             size,
             arrived: 0,
+            generation: 0,
+            wakers: Wakers::new(),
         }
     }
 }
@@ -81,24 +89,46 @@ impl RankingBarrier {
     }
 
     pub fn wait(&self, i: usize) -> usize {
-        let arrival;
+        let mut guard = self.state.lock().unwrap();
+        let (arrival, generation) = self.arrive(&mut guard);
+        println!("{} arrived {}", i, arrival);
 
-        {
-            let mut guard = self.state.lock().unwrap();
-            arrival = guard.arrived;
-            println!("{} arrived {}", i, arrival);
-            guard.arrived += 1;
-        }
-
-        {
-            let _guard = self.condvar.wait_while(self.state.lock().unwrap(), |state| {
-                state.arrived < state.size
-            }).unwrap();
-        }        
-        self.condvar.notify_one();
+        let _guard = self.condvar.wait_while(guard, |state| {
+            state.generation == generation
+        }).unwrap();
 
         arrival
     }
+
+    // Come wait, ma sospende il task invece del thread
+    #[allow(dead_code)]
+    pub async fn wait_async(&self) -> usize {
+        let mut arrival = None;
+        poll_fn(|cx| {
+            let mut state = self.state.lock().unwrap();
+            let (arrival, generation) = *arrival.get_or_insert_with(|| self.arrive(&mut state));
+            if state.generation != generation {
+                return Poll::Ready(arrival);
+            }
+            state.wakers.register(cx.waker());
+            Poll::Pending
+        }).await
+    }
+
+    // Registra un arrivo e ne restituisce l'ordine, a partire da 1, insieme al ciclo a cui
+    // appartiene, l'ultimo arrivato apre la barriera
+    fn arrive(&self, state: &mut BarrierState) -> (usize, usize) {
+        state.arrived += 1;
+        let arrival = state.arrived;
+        let generation = state.generation;
+        if state.arrived == state.size {
+            state.arrived = 0;
+            state.generation += 1;
+            self.condvar.notify_all();
+            state.wakers.wake_all();
+        }
+        (arrival, generation)
+    }
 }
 
 fn ranking_barrier() {
@@ -221,3 +251,52 @@ pub fn main() {
     ranking_barrier();
     println!("------------------");
 }
+
+#[cfg(test)]
+mod test {
+    use crate::RankingBarrier;
+    use soluzione_temi_malnati::task::block_on;
+    use std::{sync::Arc, thread};
+
+    #[test]
+    fn async_wait_is_cyclic() {
+        let barrier = Arc::new(RankingBarrier::new(3));
+        let handles: Vec<_> = (0..3).map(|_| {
+            let barrier = Arc::clone(&barrier);
+            thread::spawn(move || block_on(async {
+                let mut arrivals = vec![];
+                for _ in 0..5 {
+                    arrivals.push(barrier.wait_async().await);
+                }
+                arrivals
+            }))
+        }).collect();
+        let arrivals: Vec<Vec<usize>> = handles.into_iter().map(|handle| handle.join().unwrap()).collect();
+        // Ad ogni ciclo ciascun ordine di arrivo viene assegnato ad un solo task
+        for cycle in 0..5 {
+            let mut ranks: Vec<usize> = arrivals.iter().map(|arrivals| arrivals[cycle]).collect();
+            ranks.sort();
+            assert_eq!(ranks, vec![1, 2, 3]);
+        }
+    }
+
+    #[test]
+    fn async_and_blocking_wait_share_cycles() {
+        let barrier = Arc::new(RankingBarrier::new(2));
+        let blocking = {
+            let barrier = Arc::clone(&barrier);
+            thread::spawn(move || (0..3).map(|i| barrier.wait(i)).collect::<Vec<_>>())
+        };
+        let asynchronous = block_on(async {
+            let mut arrivals = vec![];
+            for _ in 0..3 {
+                arrivals.push(barrier.wait_async().await);
+            }
+            arrivals
+        });
+        let blocking = blocking.join().unwrap();
+        for (a, b) in asynchronous.into_iter().zip(blocking) {
+            assert_eq!(a + b, 3);
+        }
+    }
+}
@@ -119,27 +119,68 @@ fn polymorphism() {
 // tempo.
 
 use std::collections::HashMap;
+use std::future::poll_fn;
+use std::ops::{Deref, DerefMut};
+use std::sync::{MutexGuard, TryLockError};
+use std::task::Poll;
 use std::time::{Duration, Instant};
 use std::hash::Hash;
+use soluzione_temi_malnati::task::Wakers;
 
 struct Cache<K: Eq + Hash, V> {
     map: Mutex<HashMap<K, (Instant, Arc<V>)>>,
+    // Task che in get_async hanno trovato la mappa occupata
+    wakers: Mutex<Wakers>,
+}
+
+type Map<K, V> = HashMap<K, (Instant, Arc<V>)>;
+
+// Accesso esclusivo alla mappa: al rilascio sveglia i task in attesa in get_async
+struct Locked<'a, K: Eq + Hash, V> {
+    cache: &'a Cache<K, V>,
+    map: Option<MutexGuard<'a, Map<K, V>>>
+}
+
+impl<K: Eq + Hash, V> Deref for Locked<'_, K, V> {
+    type Target = Map<K, V>;
+
+    fn deref(&self) -> &Map<K, V> {
+        self.map.as_ref().unwrap()
+    }
+}
+
+impl<K: Eq + Hash, V> DerefMut for Locked<'_, K, V> {
+    fn deref_mut(&mut self) -> &mut Map<K, V> {
+        self.map.as_mut().unwrap()
+    }
+}
+
+impl<K: Eq + Hash, V> Drop for Locked<'_, K, V> {
+    fn drop(&mut self) {
+        self.map.take();
+        self.cache.wakers.lock().unwrap().wake_all();
+    }
 }
 
 impl<K: Eq + Hash, V> Cache<K,V> {
     
     pub fn new() -> Cache<K,V> {
         Cache {
-            map: Mutex::new(HashMap::new())
+            map: Mutex::new(HashMap::new()),
+            wakers: Mutex::new(Wakers::new())
         }
     }
 
+    fn lock(&self) -> Locked<'_, K, V> {
+        Locked { cache: self, map: Some(self.map.lock().unwrap()) }
+    }
+
     pub fn size(&self) -> usize {
-        self.map.lock().unwrap().len()
+        self.lock().len()
     }
 
     pub fn put(&mut self, k: K, v: V, d: Duration) {
-        let mut map = self.map.lock().unwrap();
+        let mut map = self.lock();
         let duetime = Instant::now() + d;
         let v_rc = Arc::new(v);
 
@@ -151,7 +192,7 @@ impl<K: Eq + Hash, V> Cache<K,V> {
     }
 
     pub fn renew(&self, k: &K, d: Duration) -> Option<Arc<V>> {
-        let mut map = self.map.lock().unwrap();
+        let mut map = self.lock();
         let option_v = map.get_mut(k);
 
         if let Some(v) = option_v {
@@ -163,7 +204,7 @@ impl<K: Eq + Hash, V> Cache<K,V> {
     }
 
     pub fn get(&self, k: &K) -> Option<Arc<V>> {
-        let map = self.map.lock().unwrap();
+        let map = self.lock();
         let option_v = map.get(k);
         if let Some(v) = option_v {
             return Some(Arc::clone(&v.1));
@@ -171,7 +212,27 @@ impl<K: Eq + Hash, V> Cache<K,V> {
             return None;
         }
     }
-    
+
+    // Come get, ma se la mappa è occupata sospende il task invece di bloccare il thread sul lock
+    #[allow(dead_code)]
+    pub async fn get_async(&self, k: &K) -> Option<Arc<V>> {
+        poll_fn(|cx| {
+            let map = match self.map.try_lock() {
+                Ok(map) => map,
+                Err(TryLockError::Poisoned(error)) => panic!("{}", error),
+                Err(TryLockError::WouldBlock) => {
+                    self.wakers.lock().unwrap().register(cx.waker());
+                    // Il lock potrebbe essere stato rilasciato prima della registrazione
+                    match self.map.try_lock() {
+                        Ok(map) => map,
+                        Err(_) => return Poll::Pending
+                    }
+                }
+            };
+            let locked = Locked { cache: self, map: Some(map) };
+            Poll::Ready(locked.get(k).map(|v| Arc::clone(&v.1)))
+        }).await
+    }
 }
 
 fn cache() {
@@ -188,4 +249,31 @@ pub fn main() {
     mutex();
     polymorphism();
     cache();
+}
+
+#[cfg(test)]
+mod test {
+    use crate::Cache;
+    use soluzione_temi_malnati::task::block_on;
+    use std::future::Future;
+    use std::pin::pin;
+    use std::task::{ Context, Waker };
+    use std::time::Duration;
+
+    #[test]
+    fn async_get_waits_for_the_lock() {
+        let mut cache = Cache::new();
+        cache.put(1, "one", Duration::from_secs(10));
+        assert_eq!(block_on(cache.get_async(&1)).as_deref(), Some(&"one"));
+        assert_eq!(block_on(cache.get_async(&2)), None);
+
+        // Con la mappa occupata il task resta in attesa invece di bloccare il thread
+        let locked = cache.lock();
+        let mut get = pin!(cache.get_async(&1));
+        assert!(get.as_mut().poll(&mut Context::from_waker(Waker::noop())).is_pending());
+        assert_eq!(cache.wakers.lock().unwrap().len(), 1);
+        drop(locked);
+        assert!(cache.wakers.lock().unwrap().is_empty());
+        assert_eq!(block_on(get).as_deref(), Some(&"one"));
+    }
 }
\ No newline at end of file
@@ -86,12 +86,17 @@
 // Si implementi tale struttura dati nel linguaggio Rust, avendo cura di renderne il comportamento
 // thread-safe. Si ricordi che gli oggetti di tipo Condvar offrono un meccanismo di attesa limitata nel
 // tempo, offerto dai metodi wait_timeout(...) e wait_timeout_while(...)).
+use std::future::poll_fn;
 use std::sync::{ Mutex, Condvar };
+use std::task::Poll;
 use std::time::{ Instant, Duration };
+use soluzione_temi_malnati::task::{ Deadline, Wakers };
 
 struct DelayedQueue<T: Send + Copy> {
     queue: Mutex<Vec<(T, Instant)>>,
     condvar: Condvar,
+    // Task in attesa in take_async, registrati mentre si possiede il lock della coda
+    wakers: Mutex<Wakers>,
 }
 
 impl<T: Send + Copy> DelayedQueue<T> {
@@ -100,6 +105,7 @@ impl<T: Send + Copy> DelayedQueue<T> {
         DelayedQueue {
             queue: Mutex::new(Vec::new()),
             condvar: Condvar::new(),
+            wakers: Mutex::new(Wakers::new()),
         }
     }
     
@@ -107,6 +113,14 @@ impl<T: Send + Copy> DelayedQueue<T> {
         let now = Instant::now();
         let mut queue = self.condvar.wait_timeout_while(self.queue.lock().unwrap(), i - now , |_| false ).unwrap().0;
         queue.push((t,i));
+        drop(queue);
+        // Il nuovo elemento potrebbe scadere prima di quello atteso
+        self.notify();
+    }
+
+    fn notify(&self) {
+        self.condvar.notify_all();
+        self.wakers.lock().unwrap().wake_all();
     }
 
     pub fn take(&self) -> Option<T> {
@@ -119,12 +133,39 @@ impl<T: Send + Copy> DelayedQueue<T> {
                 let _queue = self.condvar.wait_while(self.queue.lock().unwrap(), |_| nearest.1.duration_since(now) <= Duration::from_secs(0));
             }
             let item = queue.remove(0);
+            drop(queue);
+            self.notify();
             return Some(item.0);
         } else {
             return None;
         }
     }
 
+    // Attende la scadenza dell'elemento più vicino e lo restituisce, None se la coda è vuota
+    #[allow(dead_code)]
+    pub async fn take_async(&self) -> Option<T> {
+        let mut deadline = Deadline::new();
+        poll_fn(|cx| {
+            let mut queue = self.queue.lock().unwrap();
+            let Some(nearest) = (0..queue.len()).min_by_key(|&index| queue[index].1) else {
+                return Poll::Ready(None);
+            };
+            let duetime = queue[nearest].1;
+            let now = Instant::now();
+            if duetime <= now {
+                let item = queue.remove(nearest);
+                drop(queue);
+                self.notify();
+                return Poll::Ready(Some(item.0));
+            }
+            // Il timer condiviso sveglia il task alla scadenza, mentre un offer di un elemento più
+            // vicino lo sveglia prima
+            self.wakers.lock().unwrap().register(cx.waker());
+            deadline.wake_at(duetime, cx.waker());
+            Poll::Pending
+        }).await
+    }
+
     pub fn _size(&self) -> usize {
         let queue = self.queue.lock().unwrap();
         return queue.len();
@@ -152,3 +193,40 @@ pub fn main() {
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use crate::DelayedQueue;
+    use soluzione_temi_malnati::task::block_on;
+    use std::sync::Arc;
+    use std::thread;
+    use std::time::{ Duration, Instant };
+
+    #[test]
+    fn async_take_waits_for_duetime() {
+        let queue = DelayedQueue::new();
+        let start = Instant::now();
+        queue.offer(2, start + Duration::from_millis(60));
+        queue.offer(1, start + Duration::from_millis(30));
+        assert_eq!(block_on(queue.take_async()), Some(1));
+        assert!(start.elapsed() >= Duration::from_millis(30));
+        assert_eq!(block_on(queue.take_async()), Some(2));
+        assert!(start.elapsed() >= Duration::from_millis(60));
+        assert_eq!(block_on(queue.take_async()), None);
+    }
+
+    #[test]
+    fn async_take_wakes_on_earlier_offer() {
+        let queue = Arc::new(DelayedQueue::new());
+        let start = Instant::now();
+        queue.offer(2, start + Duration::from_secs(5));
+        let taker = {
+            let queue = Arc::clone(&queue);
+            thread::spawn(move || block_on(queue.take_async()))
+        };
+        thread::sleep(Duration::from_millis(20));
+        queue.offer(1, Instant::now() + Duration::from_millis(10));
+        assert_eq!(taker.join().unwrap(), Some(1));
+        assert!(start.elapsed() < Duration::from_secs(1));
+    }
+}
@@ -110,62 +110,746 @@
 // richieste.
 //
 //
+use std::collections::{ HashMap, VecDeque };
+use std::future::{ poll_fn, Future };
 use std::sync::{ Mutex, Condvar, Arc };
-use std::thread::spawn;
+use std::task::{ Poll, Waker };
+use std::thread::{ sleep, spawn };
+use std::time::{ Duration, Instant };
 use rand::{Rng, thread_rng};
+use soluzione_temi_malnati::future::{ self as promise, Promise };
+use soluzione_temi_malnati::task;
 
-struct ExecutionLimiter {
-    lock: Mutex<usize>,
-    condvar: Condvar,
+struct SemaphoreState {
+    permits: usize,
+    // Ticket dei thread in attesa, in ordine di arrivo: solo il primo della coda può acquisire,
+    // così una richiesta con peso elevato non viene scavalcata da una serie di richieste piccole
+    waiting: VecDeque<u64>,
+    next_ticket: u64,
+    // Task in attesa in acquire_async, indicizzati per ticket così ogni poll sostituisce il waker
+    // precedente; svegliati insieme ai thread sulla condvar
+    wakers: HashMap<u64, Waker>
+}
+
+struct Semaphore {
+    state: Mutex<SemaphoreState>,
+    condvar: Condvar
+}
+
+// Il permesso viene rilasciato nel Drop, quindi il conteggio viene ripristinato sia quando f
+// termina normalmente sia quando f va in panic (durante l'unwinding)
+struct Permit<'a> {
+    semaphore: &'a Semaphore,
     n: usize
 }
 
+impl Drop for Permit<'_> {
+    fn drop(&mut self) {
+        if self.n > 0 {
+            self.semaphore.add_permits(self.n);
+        }
+    }
+}
+
+#[allow(dead_code)]
+impl Permit<'_> {
+    pub fn count(&self) -> usize {
+        self.n
+    }
+
+    // I permessi non vengono restituiti al semaforo alla distruzione del Permit
+    pub fn forget(mut self) {
+        self.n = 0;
+    }
+}
+
+#[allow(dead_code)]
+impl Semaphore {
+
+    pub fn new(permits: usize) -> Semaphore {
+        Semaphore {
+            state: Mutex::new(SemaphoreState {
+                permits,
+                waiting: VecDeque::new(),
+                next_ticket: 0,
+                wakers: HashMap::new()
+            }),
+            condvar: Condvar::new()
+        }
+    }
+
+    pub fn available_permits(&self) -> usize {
+        self.state.lock().unwrap().permits
+    }
+
+    pub fn acquire(&self, n: usize) -> Permit<'_> {
+        let mut state = self.state.lock().unwrap();
+        let ticket = state.enqueue();
+        state = self.condvar.wait_while(state, |state| !state.can_acquire(ticket, n)).unwrap();
+        state.take(n);
+        // Il prossimo della coda potrebbe essere già in grado di proseguire
+        self.notify(&mut state);
+        Permit { semaphore: self, n }
+    }
+
+    // Come acquire, ma sospende il task invece del thread. Se il future viene scartato prima di
+    // ottenere i permessi il suo ticket viene tolto dalla coda
+    pub async fn acquire_async(&self, n: usize) -> Permit<'_> {
+        let mut waiting = Waiting { semaphore: self, ticket: None };
+        poll_fn(|cx| {
+            let mut state = self.state.lock().unwrap();
+            let ticket = *waiting.ticket.get_or_insert_with(|| state.enqueue());
+            if state.can_acquire(ticket, n) {
+                state.take(n);
+                waiting.ticket = None;
+                self.notify(&mut state);
+                return Poll::Ready(Permit { semaphore: self, n });
+            }
+            state.wakers.insert(ticket, cx.waker().clone());
+            Poll::Pending
+        }).await
+    }
+
+    pub fn try_acquire(&self, n: usize) -> Option<Permit<'_>> {
+        let mut state = self.state.lock().unwrap();
+        if state.waiting.is_empty() && state.permits >= n {
+            state.permits -= n;
+            Some(Permit { semaphore: self, n })
+        } else {
+            None
+        }
+    }
+
+    pub fn acquire_timeout(&self, n: usize, d: Duration) -> Option<Permit<'_>> {
+        let deadline = Instant::now() + d;
+        let mut state = self.state.lock().unwrap();
+        let ticket = state.enqueue();
+        loop {
+            if state.can_acquire(ticket, n) {
+                state.take(n);
+                self.notify(&mut state);
+                return Some(Permit { semaphore: self, n });
+            }
+            let now = Instant::now();
+            if now >= deadline {
+                state.waiting.retain(|&t| t != ticket);
+                // Chi era in coda dietro di noi potrebbe ora essere il primo
+                self.notify(&mut state);
+                return None;
+            }
+            state = self.condvar.wait_timeout(state, deadline - now).unwrap().0;
+        }
+    }
+
+    pub fn add_permits(&self, n: usize) {
+        let mut state = self.state.lock().unwrap();
+        state.permits += n;
+        self.notify(&mut state);
+    }
+
+    // Rimuove fino a n permessi disponibili, restituisce quanti ne sono stati effettivamente rimossi
+    pub fn forget_permits(&self, n: usize) -> usize {
+        let mut state = self.state.lock().unwrap();
+        let forgotten = n.min(state.permits);
+        state.permits -= forgotten;
+        forgotten
+    }
+
+    fn notify(&self, state: &mut SemaphoreState) {
+        self.condvar.notify_all();
+        for (_, waker) in state.wakers.drain() {
+            waker.wake();
+        }
+    }
+}
+
+// Ticket di un acquire_async non ancora soddisfatto
+struct Waiting<'a> {
+    semaphore: &'a Semaphore,
+    ticket: Option<u64>
+}
+
+impl Drop for Waiting<'_> {
+    fn drop(&mut self) {
+        if let Some(ticket) = self.ticket {
+            let mut state = self.semaphore.state.lock().unwrap();
+            state.waiting.retain(|&t| t != ticket);
+            state.wakers.remove(&ticket);
+            self.semaphore.notify(&mut state);
+        }
+    }
+}
+
+impl SemaphoreState {
+    fn enqueue(&mut self) -> u64 {
+        let ticket = self.next_ticket;
+        self.next_ticket += 1;
+        self.waiting.push_back(ticket);
+        ticket
+    }
+
+    fn can_acquire(&self, ticket: u64, n: usize) -> bool {
+        self.waiting.front() == Some(&ticket) && self.permits >= n
+    }
+
+    fn take(&mut self, n: usize) {
+        self.waiting.pop_front();
+        self.permits -= n;
+    }
+}
+
+// Limita il throughput (al massimo rate chiamate al secondo con raffiche di al più burst chiamate)
+// invece del numero di esecuzioni contemporanee
+enum RateState {
+    // Secchio di gettoni: si riempie a velocità costante fino a burst, ogni chiamata ne consuma uno
+    TokenBucket { tokens: f64, last: Instant },
+    // Generic Cell Rate Algorithm: si mantiene solo l'istante teorico di arrivo della prossima
+    // chiamata, è equivalente al leaky bucket
+    Gcra { tat: Instant }
+}
+
+struct RateLimiter {
+    state: Mutex<RateState>,
+    interval: Duration,
+    burst: u32
+}
+
+#[allow(dead_code)]
+impl RateLimiter {
+
+    pub fn token_bucket(rate: f64, burst: u32) -> RateLimiter {
+        RateLimiter::with_state(rate, burst, RateState::TokenBucket {
+            tokens: burst as f64,
+            last: Instant::now()
+        })
+    }
+
+    pub fn gcra(rate: f64, burst: u32) -> RateLimiter {
+        RateLimiter::with_state(rate, burst, RateState::Gcra { tat: Instant::now() })
+    }
+
+    fn with_state(rate: f64, burst: u32, state: RateState) -> RateLimiter {
+        assert!(rate > 0.0 && burst > 0, "rate e burst devono essere positivi");
+        RateLimiter {
+            state: Mutex::new(state),
+            interval: Duration::from_secs_f64(1.0 / rate),
+            burst
+        }
+    }
+
+    // Tempo da attendere prima che una chiamata possa essere ammessa, zero se lo è già
+    pub fn until_ready(&self) -> Duration {
+        self.wait_time(&mut self.state.lock().unwrap(), Instant::now())
+    }
+
+    pub fn try_acquire(&self) -> bool {
+        let mut state = self.state.lock().unwrap();
+        let now = Instant::now();
+        if self.wait_time(&mut state, now).is_zero() {
+            self.consume(&mut state, now);
+            true
+        } else {
+            false
+        }
+    }
+
+    pub fn acquire(&self) {
+        loop {
+            let wait = {
+                let mut state = self.state.lock().unwrap();
+                let now = Instant::now();
+                let wait = self.wait_time(&mut state, now);
+                if wait.is_zero() {
+                    self.consume(&mut state, now);
+                    return;
+                }
+                wait
+            };
+            // Si dorme senza mantenere il lock, un altro thread potrebbe consumare il gettone nel
+            // frattempo quindi al risveglio si ricontrolla
+            sleep(wait);
+        }
+    }
+
+    pub async fn acquire_async(&self) {
+        loop {
+            let wait = {
+                let mut state = self.state.lock().unwrap();
+                let now = Instant::now();
+                let wait = self.wait_time(&mut state, now);
+                if wait.is_zero() {
+                    self.consume(&mut state, now);
+                    return;
+                }
+                wait
+            };
+            task::sleep(wait).await;
+        }
+    }
+
+    fn wait_time(&self, state: &mut RateState, now: Instant) -> Duration {
+        match state {
+            RateState::TokenBucket { tokens, last } => {
+                let elapsed = now.saturating_duration_since(*last).as_secs_f64();
+                *tokens = (*tokens + elapsed / self.interval.as_secs_f64()).min(self.burst as f64);
+                *last = now;
+                if *tokens >= 1.0 {
+                    Duration::ZERO
+                } else {
+                    self.interval.mul_f64(1.0 - *tokens)
+                }
+            },
+            RateState::Gcra { tat } => {
+                let tolerance = self.interval * (self.burst - 1);
+                tat.checked_sub(tolerance)
+                    .map_or(Duration::ZERO, |allowed_at| allowed_at.saturating_duration_since(now))
+            }
+        }
+    }
+
+    fn consume(&self, state: &mut RateState, now: Instant) {
+        match state {
+            RateState::TokenBucket { tokens, .. } => *tokens -= 1.0,
+            RateState::Gcra { tat } => *tat = (*tat).max(now) + self.interval
+        }
+    }
+}
+
+// Strategie per adattare il limite di esecuzioni contemporanee a partire dalla latenza osservata
+// e dagli errori delle funzioni eseguite
+#[allow(dead_code)]
+enum LimitAlgorithm {
+    // Additive increase / multiplicative decrease: il limite cresce di 1 per ogni "finestra" di
+    // esecuzioni riuscite e viene moltiplicato per backoff a fronte di un errore o di una latenza
+    // oltre la soglia
+    Aimd { latency_threshold: Duration, backoff: f64 },
+    // Stile TCP Vegas: dal rapporto tra latenza minima osservata e latenza attuale si stima quante
+    // esecuzioni sono "in coda", il limite cresce se sono meno di alpha e cala se sono più di beta
+    Vegas { alpha: f64, beta: f64, backoff: f64 }
+}
+
+struct AdaptiveState {
+    estimate: f64,
+    limit: usize,
+    // Permessi da eliminare non appena vengono restituiti, perché il limite è sceso mentre erano
+    // in uso
+    debt: usize,
+    min_latency: Option<Duration>
+}
+
+struct AdaptiveLimit {
+    state: Mutex<AdaptiveState>,
+    algorithm: LimitAlgorithm,
+    min_limit: usize,
+    max_limit: usize
+}
+
+impl AdaptiveLimit {
+    fn record(&self, semaphore: &Semaphore, permit: Permit<'_>, latency: Duration, failed: bool) {
+        let mut state = self.state.lock().unwrap();
+        let min_latency = *state.min_latency.get_or_insert(latency);
+        state.min_latency = Some(min_latency.min(latency));
+
+        let estimate = state.estimate;
+        state.estimate = match self.algorithm {
+            LimitAlgorithm::Aimd { latency_threshold, backoff } => {
+                if failed || latency > latency_threshold {
+                    estimate * backoff
+                } else {
+                    estimate + 1.0 / estimate
+                }
+            },
+            LimitAlgorithm::Vegas { alpha, beta, backoff } => {
+                let queue = estimate * (1.0 - min_latency.as_secs_f64() / latency.as_secs_f64().max(f64::EPSILON));
+                if failed {
+                    estimate * backoff
+                } else if queue < alpha {
+                    estimate + 1.0 / estimate
+                } else if queue > beta {
+                    estimate - 1.0 / estimate
+                } else {
+                    estimate
+                }
+            }
+        }.clamp(self.min_limit as f64, self.max_limit as f64);
+
+        // Il limite viene applicato aggiungendo o togliendo permessi al semaforo
+        let limit = state.estimate as usize;
+        if limit > state.limit {
+            let grow = limit - state.limit;
+            let repaid = grow.min(state.debt);
+            state.debt -= repaid;
+            semaphore.add_permits(grow - repaid);
+        } else if limit < state.limit {
+            let shrink = state.limit - limit;
+            state.debt += shrink - semaphore.forget_permits(shrink);
+        }
+        state.limit = limit;
+
+        if state.debt > 0 {
+            state.debt -= 1;
+            permit.forget();
+        }
+    }
+}
+
+type Job = Box<dyn FnOnce(&ExecutionLimiter) + Send>;
+
+// Esecuzioni richieste con submit e thread che le stanno smaltendo
+struct Submitted {
+    jobs: VecDeque<Job>,
+    workers: usize
+}
+
+struct ExecutionLimiter {
+    semaphore: Semaphore,
+    rate: Option<RateLimiter>,
+    adaptive: Option<AdaptiveLimit>,
+    submitted: Mutex<Submitted>,
+    // Più thread di quante esecuzioni possono essere contemporanee resterebbero solo in attesa
+    max_workers: usize
+}
+
+// Misura una singola esecuzione: nel Drop la latenza e l'esito vengono comunicati al limite
+// adattivo, anche quando f va in panic
+struct Sample<'a> {
+    limiter: &'a ExecutionLimiter,
+    permit: Option<Permit<'a>>,
+    start: Instant,
+    failed: bool
+}
+
+impl Drop for Sample<'_> {
+    fn drop(&mut self) {
+        if let (Some(adaptive), Some(permit)) = (&self.limiter.adaptive, self.permit.take()) {
+            adaptive.record(&self.limiter.semaphore, permit, self.start.elapsed(), self.failed);
+        }
+    }
+}
+
+impl Submitted {
+    fn new() -> Submitted {
+        Submitted { jobs: VecDeque::new(), workers: 0 }
+    }
+}
+
 impl ExecutionLimiter {
     
     pub fn new(n: usize) -> ExecutionLimiter {
         ExecutionLimiter {
-            lock: Mutex::new(0),
-            condvar: Condvar::new(),
-            n
-        }
-    }
-
-    pub fn execute<R: Sized>(&mut self, f: impl Fn() -> Result<R, ()>) -> Option<R> {
-        let mut current_n_threads = self.condvar
-        .wait_while(
-            self.lock.lock().unwrap(),
-            |n_threads_active| *n_threads_active > self.n
-        ).unwrap();
-        *current_n_threads += 1;
-        if let Ok(result) = f() {
-            *current_n_threads -= 1;
-            return Some(result);
-        } else {
-            *current_n_threads -= 1;
-            return None;
+            semaphore: Semaphore::new(n),
+            rate: None,
+            adaptive: None,
+            submitted: Mutex::new(Submitted::new()),
+            max_workers: n
+        }
+    }
+
+    // Oltre al massimo di n esecuzioni contemporanee impone anche il limite di throughput
+    #[allow(dead_code)]
+    pub fn with_rate(n: usize, rate: RateLimiter) -> ExecutionLimiter {
+        ExecutionLimiter {
+            semaphore: Semaphore::new(n),
+            rate: Some(rate),
+            adaptive: None,
+            submitted: Mutex::new(Submitted::new()),
+            max_workers: n
+        }
+    }
+
+    // Il limite parte da initial e viene adattato tra min e max in base alle esecuzioni osservate
+    #[allow(dead_code)]
+    pub fn adaptive(initial: usize, min: usize, max: usize, algorithm: LimitAlgorithm) -> ExecutionLimiter {
+        assert!(min > 0 && min <= initial && initial <= max, "deve valere 0 < min <= initial <= max");
+        ExecutionLimiter {
+            semaphore: Semaphore::new(initial),
+            rate: None,
+            adaptive: Some(AdaptiveLimit {
+                state: Mutex::new(AdaptiveState {
+                    estimate: initial as f64,
+                    limit: initial,
+                    debt: 0,
+                    min_latency: None
+                }),
+                algorithm,
+                min_limit: min,
+                max_limit: max
+            }),
+            submitted: Mutex::new(Submitted::new()),
+            max_workers: max
         }
     }
 
+    #[allow(dead_code)]
+    pub fn current_limit(&self) -> Option<usize> {
+        self.adaptive.as_ref().map(|adaptive| adaptive.state.lock().unwrap().limit)
+    }
+
+    pub fn execute<R>(&self, f: impl FnOnce() -> R) -> R {
+        self.run(f, |_| false)
+    }
+
+    // Come execute, ma un Err viene contato come fallimento dal limite adattivo
+    #[allow(dead_code)]
+    pub fn execute_result<T, E>(&self, f: impl FnOnce() -> Result<T, E>) -> Result<T, E> {
+        self.run(f, Result::is_err)
+    }
+
+    fn run<R>(&self, f: impl FnOnce() -> R, is_failure: impl FnOnce(&R) -> bool) -> R {
+        // Si attende il rate limiter prima del semaforo, così chi aspetta il proprio turno non
+        // occupa uno degli n slot
+        if let Some(rate) = &self.rate {
+            rate.acquire();
+        }
+        // Il lock non viene mantenuto durante l'esecuzione di f, altrimenti le invocazioni
+        // sarebbero serializzate
+        let mut sample = Sample {
+            limiter: self,
+            permit: Some(self.semaphore.acquire(1)),
+            start: Instant::now(),
+            failed: true
+        };
+        let result = f();
+        sample.failed = is_failure(&result);
+        result
+    }
+
+    // Come execute, ma f viene accodata e il risultato viene consegnato tramite il Future. Le
+    // esecuzioni in coda vengono smaltite da al più max_workers thread, creati solo quando servono
+    // e terminati quando la coda è vuota. Se il Future viene cancellato prima del turno f non
+    // viene eseguita, un panic di f viene contato come fallimento dal limite adattivo
+    #[allow(dead_code)]
+    pub fn submit<R: Send + 'static>(self: &Arc<Self>, f: impl FnOnce() -> R + Send + 'static) -> promise::Future<R> {
+        let (promise, future) = Promise::new();
+        let mut submitted = self.submitted.lock().unwrap();
+        submitted.jobs.push_back(Box::new(move |limiter: &ExecutionLimiter| {
+            limiter.run(|| promise.is_cancelled() || promise.complete_with(f), |completed| !completed);
+        }));
+        if submitted.workers < self.max_workers {
+            submitted.workers += 1;
+            let limiter = Arc::clone(self);
+            spawn(move || limiter.work());
+        }
+        future
+    }
+
+    fn work(&self) {
+        loop {
+            let job = {
+                let mut submitted = self.submitted.lock().unwrap();
+                match submitted.jobs.pop_front() {
+                    Some(job) => job,
+                    None => {
+                        submitted.workers -= 1;
+                        return;
+                    }
+                }
+            };
+            job(self);
+        }
+    }
+
+    // Come execute, ma l'attesa del turno sospende il task invece del thread. Se il future viene
+    // scartato prima di terminare l'esecuzione conta come fallita
+    #[allow(dead_code)]
+    pub async fn execute_async<R>(&self, f: impl Future<Output = R>) -> R {
+        self.run_async(f, |_| false).await
+    }
+
+    #[allow(dead_code)]
+    pub async fn execute_result_async<T, E>(&self, f: impl Future<Output = Result<T, E>>) -> Result<T, E> {
+        self.run_async(f, Result::is_err).await
+    }
+
+    async fn run_async<R>(&self, f: impl Future<Output = R>, is_failure: impl FnOnce(&R) -> bool) -> R {
+        if let Some(rate) = &self.rate {
+            rate.acquire_async().await;
+        }
+        let mut sample = Sample {
+            limiter: self,
+            permit: Some(self.semaphore.acquire_async(1).await),
+            start: Instant::now(),
+            failed: true
+        };
+        let result = f.await;
+        sample.failed = is_failure(&result);
+        result
+    }
+
+}
+
+// Stato osservabile dall'esterno del circuit breaker
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum CircuitState {
+    Closed,
+    Open,
+    HalfOpen
+}
+
+#[derive(Debug, PartialEq)]
+enum CircuitError<E> {
+    // Il circuito è aperto e la funzione non è stata eseguita
+    Rejected,
+    Failed(E)
+}
+
+enum Circuit {
+    // Le chiamate passano e il loro esito viene registrato nella finestra scorrevole
+    Closed,
+    // Le chiamate vengono rifiutate fino alla fine del periodo di raffreddamento
+    Open { until: Instant },
+    // Vengono ammesse al più probes chiamate di prova: se riescono tutte il circuito si chiude,
+    // al primo errore si riapre
+    HalfOpen { in_flight: usize, successes: usize }
+}
+
+struct BreakerState {
+    circuit: Circuit,
+    // Esiti delle ultime chiamate, true se fallite
+    window: VecDeque<bool>,
+    // Incrementato ad ogni cambio di stato, così l'esito di una chiamata ammessa in uno stato
+    // precedente non viene contato in quello nuovo
+    generation: u64
+}
+
+struct CircuitBreaker {
+    state: Mutex<BreakerState>,
+    window_size: usize,
+    failure_rate: f64,
+    cool_down: Duration,
+    probes: usize
+}
+
+// Come Sample per il limite adattivo: una chiamata che va in panic viene contata come fallita
+struct Call<'a> {
+    breaker: &'a CircuitBreaker,
+    generation: u64,
+    failed: bool
+}
+
+impl Drop for Call<'_> {
+    fn drop(&mut self) {
+        self.breaker.record(self.generation, self.failed);
+    }
+}
+
+#[allow(dead_code)]
+impl CircuitBreaker {
+
+    // Il circuito si apre quando, sulle ultime window_size chiamate, la frazione di fallimenti
+    // raggiunge failure_rate
+    pub fn new(window_size: usize, failure_rate: f64, cool_down: Duration, probes: usize) -> CircuitBreaker {
+        assert!(window_size > 0 && probes > 0, "window_size e probes devono essere positivi");
+        CircuitBreaker {
+            state: Mutex::new(BreakerState {
+                circuit: Circuit::Closed,
+                window: VecDeque::with_capacity(window_size),
+                generation: 0
+            }),
+            window_size,
+            failure_rate,
+            cool_down,
+            probes
+        }
+    }
+
+    pub fn state(&self) -> CircuitState {
+        match self.state.lock().unwrap().circuit {
+            Circuit::Closed => CircuitState::Closed,
+            Circuit::Open { until } if Instant::now() < until => CircuitState::Open,
+            Circuit::Open { .. } | Circuit::HalfOpen { .. } => CircuitState::HalfOpen
+        }
+    }
+
+    pub fn call<T, E>(&self, f: impl FnOnce() -> Result<T, E>) -> Result<T, CircuitError<E>> {
+        let mut call = Call {
+            breaker: self,
+            generation: self.admit().ok_or(CircuitError::Rejected)?,
+            failed: true
+        };
+        let result = f();
+        call.failed = result.is_err();
+        result.map_err(CircuitError::Failed)
+    }
+
+    fn admit(&self) -> Option<u64> {
+        let mut state = self.state.lock().unwrap();
+        match state.circuit {
+            Circuit::Closed => {},
+            Circuit::Open { until } => {
+                if Instant::now() < until {
+                    return None;
+                }
+                state.transition(Circuit::HalfOpen { in_flight: 1, successes: 0 });
+            },
+            Circuit::HalfOpen { ref mut in_flight, .. } => {
+                if *in_flight >= self.probes {
+                    return None;
+                }
+                *in_flight += 1;
+            }
+        }
+        Some(state.generation)
+    }
+
+    fn record(&self, generation: u64, failed: bool) {
+        let mut state = self.state.lock().unwrap();
+        if state.generation != generation {
+            return;
+        }
+        match state.circuit {
+            Circuit::Closed => {
+                if state.window.len() == self.window_size {
+                    state.window.pop_front();
+                }
+                state.window.push_back(failed);
+                let failures = state.window.iter().filter(|&&failed| failed).count();
+                if state.window.len() == self.window_size
+                    && failures as f64 >= self.failure_rate * self.window_size as f64 {
+                    state.transition(Circuit::Open { until: Instant::now() + self.cool_down });
+                }
+            },
+            Circuit::HalfOpen { ref mut in_flight, ref mut successes } => {
+                *in_flight -= 1;
+                if failed {
+                    state.transition(Circuit::Open { until: Instant::now() + self.cool_down });
+                } else {
+                    *successes += 1;
+                    if *successes >= self.probes {
+                        state.transition(Circuit::Closed);
+                    }
+                }
+            },
+            Circuit::Open { .. } => {}
+        }
+    }
+}
+
+impl BreakerState {
+    fn transition(&mut self, circuit: Circuit) {
+        self.circuit = circuit;
+        self.window.clear();
+        self.generation += 1;
+    }
 }
 
 pub fn main() {
-    let limiter = Arc::new(Mutex::new(ExecutionLimiter::new(3)));
+    let limiter = Arc::new(ExecutionLimiter::new(3));
     let function = || {
         let mut rng = thread_rng();
         let random = rng.gen_range(0..4);
         if random > 0 {
-            return Ok(random);
+            Ok(random)
         } else {
-            return Err(());
+            Err(())
         }
     };
     let mut handles = vec![];
 
     for _ in 0..5 {
-        let mut_clone = Arc::clone(&limiter);
+        let limiter_clone = Arc::clone(&limiter);
         let handle = spawn(move || {
-            let result = mut_clone.lock().unwrap().execute(function);
-            if let Some(result) = result {
+            if let Ok(result) = limiter_clone.execute(function) {
                 println!("{}",result);
             }
         });
@@ -176,3 +860,354 @@ pub fn main() {
         handle.join().unwrap();
     }
 }
+
+#[cfg(test)]
+mod test {
+    use crate::{CircuitBreaker, CircuitError, CircuitState, ExecutionLimiter, LimitAlgorithm, RateLimiter, Semaphore};
+    use rand::{Rng, thread_rng};
+    use soluzione_temi_malnati::future::{ join_all, FutureError };
+    use soluzione_temi_malnati::task::block_on;
+    use std::{collections::HashSet, future::Future, panic, pin::pin, sync::{Arc, Mutex, atomic::{AtomicUsize, Ordering}}, task::{Context, Waker}, thread::{current, sleep, spawn}, time::{Duration, Instant}};
+
+    #[test]
+    fn never_exceeds_limit() {
+        let limiter = Arc::new(ExecutionLimiter::new(3));
+        let running = Arc::new(AtomicUsize::new(0));
+        let max_running = Arc::new(AtomicUsize::new(0));
+        let mut handles = vec![];
+
+        for _ in 0..10 {
+            let limiter = Arc::clone(&limiter);
+            let running = Arc::clone(&running);
+            let max_running = Arc::clone(&max_running);
+            handles.push(spawn(move || {
+                limiter.execute(|| {
+                    let now = running.fetch_add(1, Ordering::SeqCst) + 1;
+                    max_running.fetch_max(now, Ordering::SeqCst);
+                    sleep(Duration::from_millis(20));
+                    running.fetch_sub(1, Ordering::SeqCst);
+                })
+            }));
+        }
+
+        for handle in handles {
+            handle.join().unwrap();
+        }
+        assert_eq!(max_running.load(Ordering::SeqCst), 3);
+    }
+
+    #[test]
+    fn submit_returns_futures() {
+        let limiter = Arc::new(ExecutionLimiter::new(2));
+        let running = Arc::new(AtomicUsize::new(0));
+        let max_running = Arc::new(AtomicUsize::new(0));
+        let futures = (0..6).map(|i| {
+            let running = Arc::clone(&running);
+            let max_running = Arc::clone(&max_running);
+            limiter.submit(move || {
+                let now = running.fetch_add(1, Ordering::SeqCst) + 1;
+                max_running.fetch_max(now, Ordering::SeqCst);
+                sleep(Duration::from_millis(20));
+                running.fetch_sub(1, Ordering::SeqCst);
+                i * 10
+            })
+        }).collect();
+        assert_eq!(join_all(futures).get(), Ok(vec![0, 10, 20, 30, 40, 50]));
+        assert_eq!(max_running.load(Ordering::SeqCst), 2);
+
+        assert_eq!(limiter.submit(|| -> u32 { panic!("boom") }).get(), Err(FutureError::Panicked("boom".to_string())));
+    }
+
+    #[test]
+    fn submit_reuses_a_bounded_number_of_threads() {
+        let limiter = Arc::new(ExecutionLimiter::new(2));
+        let threads = Arc::new(Mutex::new(HashSet::new()));
+        let futures = (0..50).map(|_| {
+            let threads = Arc::clone(&threads);
+            limiter.submit(move || {
+                threads.lock().unwrap().insert(current().id());
+                sleep(Duration::from_millis(1));
+            })
+        }).collect();
+        assert!(join_all(futures).get().is_ok());
+        assert!(threads.lock().unwrap().len() <= 2);
+        assert_eq!(limiter.submit(|| 7).get(), Ok(7));
+    }
+
+    #[test]
+    fn panic_releases_permit() {
+        let limiter = ExecutionLimiter::new(1);
+        let result = panic::catch_unwind(panic::AssertUnwindSafe(|| {
+            limiter.execute(|| panic!("boom"))
+        }));
+        assert!(result.is_err());
+        assert_eq!(limiter.execute(|| 42), 42);
+    }
+
+    #[test]
+    fn semaphore_weighted_and_try() {
+        let semaphore = Semaphore::new(3);
+        let permit = semaphore.acquire(2);
+        assert_eq!(semaphore.available_permits(), 1);
+        assert!(semaphore.try_acquire(2).is_none());
+        assert!(semaphore.acquire_timeout(2, Duration::from_millis(20)).is_none());
+        drop(permit);
+        assert_eq!(semaphore.try_acquire(3).map(|p| p.count()), Some(3));
+        assert_eq!(semaphore.available_permits(), 3);
+    }
+
+    #[test]
+    fn semaphore_add_and_forget() {
+        let semaphore = Semaphore::new(2);
+        assert_eq!(semaphore.forget_permits(5), 2);
+        assert!(semaphore.try_acquire(1).is_none());
+        semaphore.add_permits(1);
+        semaphore.acquire(1).forget();
+        assert_eq!(semaphore.available_permits(), 0);
+    }
+
+    #[test]
+    fn semaphore_is_fifo_fair() {
+        let semaphore = Arc::new(Semaphore::new(2));
+        let held = semaphore.acquire(1);
+
+        // Una richiesta da 2 permessi si mette in coda...
+        let big = {
+            let semaphore = Arc::clone(&semaphore);
+            spawn(move || {
+                let _permit = semaphore.acquire(2);
+            })
+        };
+        sleep(Duration::from_millis(20));
+        // ...e le richieste più piccole arrivate dopo non possono scavalcarla
+        assert!(semaphore.try_acquire(1).is_none());
+        assert!(semaphore.acquire_timeout(1, Duration::from_millis(20)).is_none());
+
+        drop(held);
+        big.join().unwrap();
+        assert_eq!(semaphore.available_permits(), 2);
+    }
+
+    #[test]
+    fn token_bucket_allows_burst_then_throttles() {
+        let limiter = RateLimiter::token_bucket(20.0, 3);
+        assert!((0..3).all(|_| limiter.try_acquire()));
+        assert!(!limiter.try_acquire());
+        let wait = limiter.until_ready();
+        assert!(wait > Duration::ZERO && wait <= Duration::from_millis(50));
+    }
+
+    #[test]
+    fn gcra_allows_burst_then_throttles() {
+        let limiter = RateLimiter::gcra(20.0, 3);
+        assert!((0..3).all(|_| limiter.try_acquire()));
+        assert!(!limiter.try_acquire());
+        let wait = limiter.until_ready();
+        assert!(wait > Duration::ZERO && wait <= Duration::from_millis(50));
+    }
+
+    #[test]
+    fn limiter_enforces_rate() {
+        let limiter = ExecutionLimiter::with_rate(2, RateLimiter::gcra(50.0, 1));
+        let start = Instant::now();
+        for _ in 0..6 {
+            limiter.execute(|| ());
+        }
+        // La prima chiamata è immediata, le altre 5 distanziate di 20ms
+        assert!(start.elapsed() >= Duration::from_millis(95));
+    }
+
+    #[test]
+    fn aimd_backs_off_on_failures() {
+        let limiter = ExecutionLimiter::adaptive(8, 1, 16, LimitAlgorithm::Aimd {
+            latency_threshold: Duration::from_secs(1),
+            backoff: 0.5
+        });
+        for _ in 0..3 {
+            let _ = limiter.execute_result(|| Err::<(), ()>(()));
+        }
+        assert_eq!(limiter.current_limit(), Some(1));
+        for _ in 0..20 {
+            let _ = limiter.execute_result(|| Ok::<(), ()>(()));
+        }
+        assert!(limiter.current_limit().unwrap() > 1);
+    }
+
+    #[test]
+    fn aimd_counts_panics_as_failures() {
+        let limiter = ExecutionLimiter::adaptive(4, 1, 4, LimitAlgorithm::Aimd {
+            latency_threshold: Duration::from_secs(1),
+            backoff: 0.5
+        });
+        let result = panic::catch_unwind(panic::AssertUnwindSafe(|| {
+            limiter.execute(|| panic!("boom"))
+        }));
+        assert!(result.is_err());
+        assert_eq!(limiter.current_limit(), Some(2));
+        assert!(limiter.semaphore.try_acquire(3).is_none());
+        assert!(limiter.semaphore.try_acquire(2).is_some());
+    }
+
+    #[test]
+    fn vegas_shrinks_when_latency_grows() {
+        let limiter = ExecutionLimiter::adaptive(10, 2, 20, LimitAlgorithm::Vegas {
+            alpha: 2.0,
+            beta: 4.0,
+            backoff: 0.5
+        });
+        limiter.execute(|| ());
+        for _ in 0..10 {
+            limiter.execute(|| sleep(Duration::from_millis(5)));
+        }
+        assert!(limiter.current_limit().unwrap() < 10);
+    }
+
+    #[test]
+    fn adaptive_random_failures_stay_within_bounds() {
+        let limiter = Arc::new(ExecutionLimiter::adaptive(3, 1, 6, LimitAlgorithm::Aimd {
+            latency_threshold: Duration::from_millis(50),
+            backoff: 0.7
+        }));
+        let running = Arc::new(AtomicUsize::new(0));
+        let mut handles = vec![];
+
+        for _ in 0..8 {
+            let limiter = Arc::clone(&limiter);
+            let running = Arc::clone(&running);
+            handles.push(spawn(move || {
+                for _ in 0..20 {
+                    let _ = limiter.execute_result(|| {
+                        assert!(running.fetch_add(1, Ordering::SeqCst) < 6);
+                        let random = thread_rng().gen_range(0..4);
+                        running.fetch_sub(1, Ordering::SeqCst);
+                        if random > 0 { Ok(random) } else { Err(()) }
+                    });
+                }
+            }));
+        }
+
+        for handle in handles {
+            handle.join().unwrap();
+        }
+        let limit = limiter.current_limit().unwrap();
+        assert!((1..=6).contains(&limit));
+        // Tutti i permessi sono stati restituiti: ne sono disponibili esattamente quanti il limite
+        assert_eq!(limiter.semaphore.available_permits() + limiter.adaptive.as_ref().unwrap().state.lock().unwrap().debt, limit);
+    }
+
+    #[test]
+    fn breaker_opens_and_rejects() {
+        let breaker = CircuitBreaker::new(4, 0.5, Duration::from_secs(60), 1);
+        let mut executed = 0;
+        for i in 0..4 {
+            let _ = breaker.call(|| if i % 2 == 0 { Ok(i) } else { Err(i) });
+        }
+        assert_eq!(breaker.state(), CircuitState::Open);
+        let result: Result<(), CircuitError<()>> = breaker.call(|| {
+            executed += 1;
+            Ok(())
+        });
+        assert_eq!(result, Err(CircuitError::Rejected));
+        assert_eq!(executed, 0);
+    }
+
+    #[test]
+    fn breaker_half_open_probes() {
+        let breaker = CircuitBreaker::new(2, 1.0, Duration::from_millis(20), 2);
+        for _ in 0..2 {
+            assert_eq!(breaker.call(|| Err::<(), _>("down")), Err(CircuitError::Failed("down")));
+        }
+        assert_eq!(breaker.state(), CircuitState::Open);
+        sleep(Duration::from_millis(30));
+        assert_eq!(breaker.state(), CircuitState::HalfOpen);
+
+        // Una prova fallita riapre il circuito
+        assert!(breaker.call(|| Err::<(), _>(())).is_err());
+        assert_eq!(breaker.state(), CircuitState::Open);
+        sleep(Duration::from_millis(30));
+
+        // Servono probes prove riuscite per richiuderlo
+        assert_eq!(breaker.call(|| Ok::<_, ()>(1)), Ok(1));
+        assert_eq!(breaker.state(), CircuitState::HalfOpen);
+        assert_eq!(breaker.call(|| Ok::<_, ()>(2)), Ok(2));
+        assert_eq!(breaker.state(), CircuitState::Closed);
+    }
+
+    #[test]
+    fn breaker_limits_concurrent_probes() {
+        let breaker = Arc::new(CircuitBreaker::new(1, 1.0, Duration::ZERO, 1));
+        assert!(breaker.call(|| Err::<(), ()>(())).is_err());
+
+        let probe = {
+            let breaker = Arc::clone(&breaker);
+            spawn(move || breaker.call(|| {
+                sleep(Duration::from_millis(50));
+                Ok::<_, ()>(())
+            }))
+        };
+        sleep(Duration::from_millis(10));
+        assert_eq!(breaker.call(|| Ok::<_, ()>(())), Err(CircuitError::Rejected));
+        assert_eq!(probe.join().unwrap(), Ok(()));
+        assert_eq!(breaker.state(), CircuitState::Closed);
+    }
+
+    #[test]
+    fn breaker_wraps_limiter() {
+        let limiter = ExecutionLimiter::new(2);
+        let breaker = CircuitBreaker::new(2, 1.0, Duration::from_secs(60), 1);
+        for _ in 0..2 {
+            let _ = breaker.call(|| limiter.execute_result(|| Err::<(), ()>(())));
+        }
+        // Il rifiuto avviene prima di occupare uno slot del limiter
+        assert_eq!(breaker.call(|| limiter.execute_result(|| Ok::<_, ()>(()))), Err(CircuitError::Rejected));
+        assert_eq!(limiter.semaphore.available_permits(), 2);
+    }
+
+    #[test]
+    fn async_execute_never_exceeds_limit() {
+        let limiter = Arc::new(ExecutionLimiter::new(2));
+        let running = Arc::new(AtomicUsize::new(0));
+        let max_running = Arc::new(AtomicUsize::new(0));
+        let handles: Vec<_> = (0..6).map(|_| {
+            let limiter = Arc::clone(&limiter);
+            let running = Arc::clone(&running);
+            let max_running = Arc::clone(&max_running);
+            spawn(move || block_on(limiter.execute_async(async {
+                let now = running.fetch_add(1, Ordering::SeqCst) + 1;
+                max_running.fetch_max(now, Ordering::SeqCst);
+                sleep(Duration::from_millis(20));
+                running.fetch_sub(1, Ordering::SeqCst);
+            })))
+        }).collect();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+        assert_eq!(max_running.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn dropped_async_acquire_leaves_queue() {
+        let semaphore = Semaphore::new(1);
+        let permit = semaphore.acquire(1);
+        {
+            let mut acquire = pin!(semaphore.acquire_async(1));
+            assert!(acquire.as_mut().poll(&mut Context::from_waker(Waker::noop())).is_pending());
+        }
+        drop(permit);
+        // Se il ticket fosse rimasto in coda nessuno potrebbe più acquisire
+        assert!(semaphore.try_acquire(1).is_some());
+        assert_eq!(block_on(semaphore.acquire_async(1)).count(), 1);
+    }
+
+    #[test]
+    fn async_limiter_enforces_rate() {
+        let limiter = ExecutionLimiter::with_rate(4, RateLimiter::token_bucket(50.0, 1));
+        let start = Instant::now();
+        block_on(async {
+            for i in 0..4 {
+                assert_eq!(limiter.execute_async(async move { i }).await, i);
+            }
+        });
+        assert!(start.elapsed() >= Duration::from_millis(55));
+    }
+}
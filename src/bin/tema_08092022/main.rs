@@ -0,0 +1,1629 @@
+// Domanda 1: Si definiscano le principali aree di memoria associate ad un eseguibile e si mostri, attraverso opportuni
+// esempi di codice, in quale situazione ciascuna di esse viene utilizzata.
+//
+// Le zone di memoria associate ad un eseguibile sono: stack, heap, variabili globali, costanti e
+// codice. Per esempio:
+// //VARIABILE GLOBALE
+// static R: usize = 4;
+// //COSTANTE
+// const PI: f32 = 3.14;
+//
+// pub fn main() {
+//     //HEAP
+//     let mut computed: Vec<f32> = Vec::new();
+//     //STACK
+//     let area = PI * (R * R) as f32;
+//
+//     //CODICE
+//     computed.push(area);
+// }
+// Domanda 2: Sia dato un primo valore di tipo std::cell::Cell<T> ed un secondo valore di tipo std::cell::RefCell<T>
+// (dove T fa riferimento alla medesima entità). Si indichino le differenze tra i due e le modalità di
+// occupazione della memoria (quantità, zone di memoria, ecc.).
+//
+// Il linguaggio rust consente al programmatore di anticipare un gran numero di errori in fase di compilazione piuttosto che in esecuzione.
+// Per fare ciò tutte le variabili sono soggette ad alcune regole imposte da un modulo specifico
+// del compilatore chiamato "borrow checker". Questo impone che ogni variabile segua 3 regole:
+// 1. Ogni variabile può essere posseduta da un solo scopo.
+// 2. Ogni variabile può avere multipli riferimenti in lettura se e solo se non esistono
+//    riferimenti mutabili per quella variabile.
+// 3. Ogni variabile può avere un solo riferimento mutabile se e solo se non esistono riferimenti
+//    in lettura per quella variabile.
+//
+// Tornando alla domanda il tipo Cell consente al programmatore di ignorare la seconda e la terza regola
+// elencata in fase di compilazione e rimandandone il controllo in fase di esecuzione.
+// Mentre il tipo RefCell serve a rimandare in fase di esecuzione il controllo dell'effettivo
+// lifetime di un riferimento passato a refcell.
+// Per consentire ciò il tipo Cell prende possesso del dato passato al costruttore, effettua una copia del dato sullo stack 
+// e offre metodi per la modifica del dato tramite get() e set().
+// Il tipo RefCell acquisisce il riferimento immutabile ad un dato e offre al programmatore i
+// metodi borrow() e borrow_mut() per ottenere riferimenti nuovi al dato in questione. Da notare
+// che essendo il check rimandato in fase di esecuzione è responsabilità del programmatore non
+// invocare un metodo finchè il lifetime del riferimento prodotto dall'altro è ancora valido e
+// viceversa.
+// In memoria RefCell viene rappresentato con la coppia di dati borrow, che indica se il dato è
+// stato già prestato ed un riferimento immutabile al dato, entrambi i campi sono allocati sullo
+// stack.
+//
+// Domanda 3: In un programma che utilizza una sincronizzazione basata su Condition Variable, è possibile che
+// alcune notifiche vengano perse? Se sì, perché? In entrambi i casi si produca un esempio di codice
+// che giustifichi la risposta.
+// 
+// Se il codice è scritto usando il metodo wait() è possibile che si verifichino due condizioni
+// particolari in cui le notifiche non vengono "registrate" dal thread destinatario, in letteratura
+// questi due eventi sono chiamati notifiche spurie e notifiche perse.
+// Dati tre o più thread che rappresentano logicamente un mittente ed due o più destinatari di un dato
+// il caso delle notifiche spurie o false notifiche si verifica quando uno dei destinatari verifica la condizione imposta dalla condition variable
+// ed entra nella sezione critica per effettuare il lock e durante questa fase un altro
+// destinatario più veloce acquisisce il lock, legge/consuma il dato e rilascia il lock, lasciando
+// così il "primo" destinatario nella condizione di una falsa notifica.
+// Un meccanismo introdotto per risolvere questo tipo di problema è offerto
+// da rust tramite il metodo wait_while() delle condition variable che controlla la condizione di
+// acquisizione del lock prima e dopo dell'acquisizione del lock sul dato e se il secondo controllo non ha successo non esegue la sezione critica che lavora sul dato.
+//
+// Una seconda tipologia di notifica non registrata dal destinatario è quella delle notifiche
+// perse. Una notifica persa si ha quando il mittente è particolarmente veloce
+// ed invia una seconda (o anche terza, o più) notifica mentre il destinatario sta ancora
+// processando la prima notifica. Per limitare questo comportamento la struct Condvar offre un
+// metodo wait_timeout(duration: Duration) in cui è possibile specificare un tempo massimo per
+// l'esecuzione del controllo della condizione in modo da non spendere troppo tempo e limitare il
+// numero di notifiche potenzialmente perse.
+//
+// Data la possibilità che entrambi gli eventi si verifichino in una stessa codebase esiste anche
+// un metodo wait_while_timeout() che combina entrambi gli approcci per limitare la possibilità di
+// false notifiche e notifiche perse.
+//
+// Domanda 4: In un sistema concorrente, ciascun thread può pubblicare eventi per rendere noto ad altri thread
+// quanto sta facendo.
+// Per evitare un accoppiamento stretto tra mittenti e destinatari degli eventi, si utilizza un
+// Dispatcher: questo è un oggetto thread-safe che offre il metodo 
+//  dispatch(msg: Msg) 
+// mediante il quale un messaggio di tipo generico Msg (soggetto al vincolo di essere clonabile)
+// viene reso disponibile a chiunque si sia sottoscritto. Un thread interessato a ricevere messaggi
+// può invocare il metodo 
+//  subscribe() 
+// del Dispatcher: otterrà come risultato un oggetto di tipo Subscription mediante il quale potrà
+// leggere i messaggi che da ora in poi saranno pubblicati attraverso il Dispatcher. Per ogni
+// sottoscrizione attiva, il Dispatcher mantiene internamente l'equivalente di una coda ordinata
+// (FIFO) di messaggi non ancora letti. A fronte dell'invocazione del metodo dispatch(msg:Msg), il
+// messaggio viene clonato ed inserito in ciascuna delle code esistenti.
+// L'oggetto Subscription offre il metodo bloccante 
+//  read() -> Option<Msg>
+// se nella coda corrispondente è presente almeno un messaggio, questo viene rimosso e restituito;
+// se nella coda non è presente nessun messaggio e il Dispatcher esiste ancora, l'invocazione si
+// blocca fino a che non viene inserito un nuovo messaggio; se invece il Dispatcher è stato distrutto,
+// viene restituito il valore corrispondente all'opzione vuota.
+// Gli oggetti Dispatcher e Subscription sono in qualche modo collegati, ma devono poter avere cicli
+// di vita indipendenti: la distruzione del Dispatcher non deve impedire la consumazione dei
+// messaggi già recapitati ad una Subscription, ma non ancora letti; parimenti, la distruzione di una
+// Subscription non deve impedire al Dispatcher di consegnare ulteriori messaggi alle eventuali altre
+// Subscription presenti.
+// Si implementino le strutture dati Dispatcher e Subscription, a scelta, nel linguaggio Rust o C++11.
+mod net;
+mod storage;
+
+use std::collections::{ BTreeMap, BTreeSet, HashMap, VecDeque };
+use std::fmt;
+use std::io;
+use std::sync::{ Arc, Condvar, Mutex };
+use std::time::{ Duration, Instant, SystemTime };
+use storage::{ Codec, LogConfig, Record, SegmentLog };
+
+// Il contenuto è condiviso tramite Arc: il Dispatcher lo memorizza una sola volta e ogni
+// destinatario ne riceve un riferimento, senza clonare il payload
+#[derive(Clone, Debug)]
+#[allow(dead_code)]
+struct Msg<T: Clone + Sync + 'static> {
+    message: Arc<T>,
+    // Impostato dal Dispatcher: utile a chi si sottoscrive con un pattern per sapere su quale
+    // topic è stato pubblicato il messaggio
+    topic: Arc<str>,
+    // Numero di sequenza assegnato dal Dispatcher, strettamente crescente: permette di
+    // riprendere la lettura con subscribe_from(Offset::At(seq + 1)) senza buchi né duplicati
+    seq: u64,
+    // Presenti solo per i messaggi inviati con Dispatcher::request
+    correlation_id: Option<u64>,
+    reply_to: Option<Arc<ReplySlot<T>>>
+}
+
+#[allow(dead_code)]
+impl <T: Clone + Sync + 'static> Msg<T> {
+    fn new(message: T) -> Self {
+        Msg {
+            message: Arc::new(message),
+            topic: "".into(),
+            seq: 0,
+            correlation_id: None,
+            reply_to: None
+        }
+    }
+
+    // Risponde ad una richiesta: solo la prima risposta viene consegnata al richiedente, le altre
+    // (e quelle a messaggi che non sono richieste) vengono scartate restituendo false
+    fn reply(&self, value: T) -> bool {
+        self.reply_to.as_ref().is_some_and(|slot| slot.fill(value))
+    }
+}
+
+// Canale monouso su cui il richiedente attende la risposta
+#[allow(dead_code)]
+struct ReplySlot<T> {
+    value: Mutex<Option<T>>,
+    condvar: Condvar
+}
+
+#[allow(dead_code)]
+impl <T> ReplySlot<T> {
+    fn new() -> Self {
+        ReplySlot {
+            value: Mutex::new(None),
+            condvar: Condvar::new()
+        }
+    }
+
+    fn fill(&self, value: T) -> bool {
+        let mut slot = self.value.lock().unwrap();
+        if slot.is_some() {
+            return false;
+        }
+        *slot = Some(value);
+        self.condvar.notify_all();
+        true
+    }
+
+    fn wait(&self, timeout: Duration) -> Option<T> {
+        let (mut slot, _) = self.condvar.wait_timeout_while(self.value.lock().unwrap(), timeout, |slot| slot.is_none()).unwrap();
+        slot.take()
+    }
+}
+
+impl <T> fmt::Debug for ReplySlot<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("ReplySlot")
+    }
+}
+
+#[derive(Debug)]
+#[allow(dead_code)]
+enum RequestError {
+    // Nessuna sottoscrizione ha ricevuto la richiesta
+    NoSubscribers,
+    Timeout,
+    Io(io::Error)
+}
+
+impl fmt::Display for RequestError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RequestError::NoSubscribers => write!(f, "no subscriber received the request"),
+            RequestError::Timeout => write!(f, "no reply before the timeout"),
+            RequestError::Io(e) => write!(f, "log write failed: {}", e)
+        }
+    }
+}
+
+impl std::error::Error for RequestError {}
+
+#[derive(Debug, PartialEq)]
+enum TopicError {
+    // Il livello vuoto, come in "orders..created"
+    EmptyLevel,
+    // '#' può comparire solo come ultimo livello
+    MisplacedWildcard,
+    // Le wildcard devono occupare un intero livello, come in "orders.*" e non "orders.cr*"
+    PartialWildcard
+}
+
+impl fmt::Display for TopicError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TopicError::EmptyLevel => write!(f, "topic with an empty level"),
+            TopicError::MisplacedWildcard => write!(f, "'#' is only allowed as the last level"),
+            TopicError::PartialWildcard => write!(f, "wildcards must span a whole level")
+        }
+    }
+}
+
+impl std::error::Error for TopicError {}
+
+// I topic sono gerarchici con i livelli separati da '.', il topic vuoto non ha livelli
+fn topic_levels(topic: &str) -> impl Iterator<Item = &str> {
+    topic.split('.').filter(|_| !topic.is_empty())
+}
+
+fn parse_pattern(pattern: &str) -> Result<Vec<String>, TopicError> {
+    let levels: Vec<String> = topic_levels(pattern).map(String::from).collect();
+    for (i, level) in levels.iter().enumerate() {
+        if level.is_empty() {
+            return Err(TopicError::EmptyLevel);
+        }
+        if level == "#" && i != levels.len() - 1 {
+            return Err(TopicError::MisplacedWildcard);
+        }
+        if level.len() > 1 && (level.contains('*') || level.contains('#')) {
+            return Err(TopicError::PartialWildcard);
+        }
+    }
+    Ok(levels)
+}
+
+fn pattern_matches(pattern: &[String], topic: &[&str]) -> bool {
+    match (pattern.split_first(), topic.split_first()) {
+        (Some((level, _)), _) if level == "#" => true,
+        (Some((level, pattern)), Some((topic_level, topic))) => {
+            (level == "*" || level == topic_level) && pattern_matches(pattern, topic)
+        },
+        (None, None) => true,
+        _ => false
+    }
+}
+
+// Trie dei pattern sottoscritti: ogni nodo corrisponde ad un prefisso di pattern e contiene gli
+// id delle sottoscrizioni il cui pattern termina lì. '*' corrisponde ad esattamente un livello,
+// '#' a zero o più livelli
+#[derive(Default)]
+struct TopicNode {
+    children: HashMap<String, TopicNode>,
+    subscribers: Vec<u64>
+}
+
+impl TopicNode {
+    fn insert(&mut self, pattern: &[String], id: u64) {
+        match pattern.split_first() {
+            None => self.subscribers.push(id),
+            Some((level, rest)) => self.children.entry(level.clone()).or_default().insert(rest, id)
+        }
+    }
+
+    fn remove(&mut self, pattern: &[String], id: u64) {
+        match pattern.split_first() {
+            None => self.subscribers.retain(|&subscriber| subscriber != id),
+            Some((level, rest)) => {
+                if let Some(child) = self.children.get_mut(level) {
+                    child.remove(rest, id);
+                    if child.subscribers.is_empty() && child.children.is_empty() {
+                        self.children.remove(level);
+                    }
+                }
+            }
+        }
+    }
+
+    fn matches(&self, topic: &[&str], found: &mut Vec<u64>) {
+        if let Some(any) = self.children.get("#") {
+            found.extend_from_slice(&any.subscribers);
+        }
+        match topic.split_first() {
+            None => found.extend_from_slice(&self.subscribers),
+            Some((level, rest)) => {
+                if let Some(child) = self.children.get(*level) {
+                    child.matches(rest, found);
+                }
+                if let Some(child) = self.children.get("*") {
+                    child.matches(rest, found);
+                }
+            }
+        }
+    }
+}
+
+// Cosa fare quando la coda di una sottoscrizione limitata è piena
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[allow(dead_code)]
+enum Overflow {
+    // Il Dispatcher attende che il destinatario legga almeno un messaggio
+    Block,
+    // Il nuovo messaggio viene scartato
+    DropNewest,
+    // Il messaggio più vecchio in coda viene scartato per fare spazio al nuovo
+    DropOldest,
+    // La sottoscrizione viene chiusa: il destinatario può leggere quanto già in coda e poi riceve
+    // Closed
+    Disconnect
+}
+
+#[derive(Debug, PartialEq)]
+enum RecvError {
+    // Il destinatario era troppo lento e ha perso questo numero di messaggi
+    Lagged(u64),
+    // Il Dispatcher è stato distrutto o la sottoscrizione chiusa e non restano messaggi da leggere
+    Closed
+}
+
+// Per quanto tempo i messaggi restano disponibili per chi si sottoscrive in ritardo, anche dopo
+// essere stati letti da tutti i destinatari
+#[derive(Clone, Copy, Debug)]
+#[allow(dead_code)]
+enum Retention {
+    Messages(usize),
+    Age(Duration)
+}
+
+// Da dove inizia a leggere una nuova sottoscrizione
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[allow(dead_code)]
+enum Offset {
+    // Dal messaggio più vecchio ancora conservato
+    Earliest,
+    // Solo i messaggi pubblicati da ora in poi
+    Latest,
+    // Dal messaggio con questo numero di sequenza
+    At(u64)
+}
+
+// Predicato valutato dal Dispatcher al momento della pubblicazione
+type Filter<T> = Box<dyn Fn(&T) -> bool + Send>;
+
+// Come scegliere il membro di un gruppo a cui recapitare un messaggio
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+#[allow(dead_code)]
+enum Balance {
+    // A turno, nell'ordine di sottoscrizione
+    #[default]
+    RoundRobin,
+    // Al membro con meno messaggi da leggere o da confermare
+    LeastLoaded
+}
+
+#[derive(Default)]
+struct Group {
+    balance: Balance,
+    // Ultimo membro scelto con RoundRobin
+    last: Option<u64>
+}
+
+impl Group {
+    // candidates sono i membri interessati al messaggio, in ordine crescente di id. La scelta va
+    // registrata in last solo quando il messaggio viene effettivamente pubblicato
+    fn choose<T: Clone + Sync + 'static>(&self, candidates: &[u64], cursors: &HashMap<u64, Cursor<T>>) -> u64 {
+        // Un membro con la coda piena riceve il messaggio solo se lo sono anche tutti gli altri
+        let available: Vec<u64> = candidates.iter().copied().filter(|id| !cursors[id].is_full()).collect();
+        let candidates = if available.is_empty() { candidates } else { &available };
+        match self.balance {
+            Balance::RoundRobin => self.last
+                .and_then(|last| candidates.iter().copied().find(|&id| id > last))
+                .unwrap_or(candidates[0]),
+            Balance::LeastLoaded => candidates.iter().copied()
+                .min_by_key(|id| (cursors[id].load(), *id))
+                .unwrap()
+        }
+    }
+}
+
+// Un messaggio pubblicato viene scritto una sola volta nel buffer circolare condiviso, insieme
+// agli id delle sottoscrizioni a cui è destinato (in ordine crescente)
+struct Entry<T: Clone + Sync + 'static> {
+    seq: u64,
+    msg: Msg<T>,
+    recipients: Vec<u64>,
+    published: Instant
+}
+
+// Posizione di lettura di una sottoscrizione nel buffer
+struct Cursor<T: Clone + Sync + 'static> {
+    pattern: Vec<String>,
+    filter: Option<Filter<T>>,
+    // Numero di sequenza del prossimo messaggio da esaminare
+    next: u64,
+    // Messaggi destinati alla sottoscrizione e non ancora letti
+    pending: usize,
+    lagged: u64,
+    capacity: Option<usize>,
+    overflow: Overflow,
+    // Consegna almeno una volta: i messaggi letti e non ancora confermati, con l'istante dopo il
+    // quale vengono consegnati di nuovo
+    visibility: Option<Duration>,
+    in_flight: BTreeMap<u64, Instant>,
+    // Messaggi ceduti da un membro uscito dal gruppo che la posizione di lettura ha già superato
+    handed_over: BTreeSet<u64>,
+    // Gruppo di cui la sottoscrizione è membro: ogni messaggio va ad un solo membro del gruppo
+    group: Option<Arc<str>>,
+    // La sottoscrizione è stata annullata: non riceve nuovi messaggi ma può leggere quelli pendenti
+    closed: bool
+}
+
+// Parametri di una nuova sottoscrizione, i metodi subscribe_* ne impostano solo alcuni
+struct SubscribeOptions<T> {
+    filter: Option<Filter<T>>,
+    capacity: Option<usize>,
+    overflow: Overflow,
+    offset: Offset,
+    visibility: Option<Duration>,
+    group: Option<Arc<str>>
+}
+
+impl <T> Default for SubscribeOptions<T> {
+    fn default() -> Self {
+        SubscribeOptions {
+            filter: None,
+            capacity: None,
+            overflow: Overflow::DropOldest,
+            offset: Offset::Latest,
+            visibility: None,
+            group: None
+        }
+    }
+}
+
+impl <T: Clone + Sync + 'static> Cursor<T> {
+    fn is_full(&self) -> bool {
+        self.capacity.is_some_and(|capacity| self.pending >= capacity)
+    }
+
+    fn load(&self) -> usize {
+        self.pending + self.in_flight.len() + self.handed_over.len()
+    }
+}
+
+// Log su disco: ogni messaggio viene scritto prima di essere reso disponibile ai destinatari
+struct Persistence<T> {
+    log: SegmentLog,
+    encode: fn(&T, &mut Vec<u8>)
+}
+
+impl <T: Clone + Sync + 'static> Persistence<T> {
+    fn append(&mut self, msg: &Msg<T>, published: SystemTime) -> io::Result<()> {
+        let mut payload = vec![];
+        (self.encode)(&msg.message, &mut payload);
+        self.log.append(&Record {
+            seq: msg.seq,
+            published,
+            topic: msg.topic.to_string(),
+            payload
+        })
+    }
+}
+
+#[allow(dead_code)]
+struct RingState<T: Clone + Sync + 'static> {
+    entries: VecDeque<Entry<T>>,
+    next_seq: u64,
+    // Numero massimo di messaggi nel buffer, quando è pieno il più vecchio viene sovrascritto
+    capacity: Option<usize>,
+    retention: Option<Retention>,
+    persistence: Option<Persistence<T>>,
+    cursors: HashMap<u64, Cursor<T>>,
+    topics: TopicNode,
+    groups: HashMap<Arc<str>, Group>,
+    next_id: u64,
+    next_correlation_id: u64,
+    closed: bool
+}
+
+impl <T: Clone + Sync + 'static> RingState<T> {
+    fn first_seq(&self) -> u64 {
+        self.entries.front().map_or(self.next_seq, |entry| entry.seq)
+    }
+
+    fn remove(&mut self, id: u64) {
+        self.leave_group(id);
+        if let Some(cursor) = self.cursors.remove(&id) {
+            self.topics.remove(&cursor.pattern, id);
+        }
+    }
+
+    // Dei destinatari individuati per un messaggio tiene un solo membro per ogni gruppo,
+    // restituendo i membri scelti
+    fn pick_group_members(&self, matching: &mut Vec<u64>) -> Vec<(Arc<str>, u64)> {
+        let mut members: HashMap<Arc<str>, Vec<u64>> = HashMap::new();
+        matching.retain(|id| match &self.cursors[id].group {
+            Some(group) => {
+                members.entry(Arc::clone(group)).or_default().push(*id);
+                false
+            },
+            None => true
+        });
+        let mut chosen = Vec::with_capacity(members.len());
+        for (name, mut candidates) in members {
+            candidates.sort_unstable();
+            let member = match self.groups.get(&name) {
+                Some(group) => group.choose(&candidates, &self.cursors),
+                None => Group::default().choose(&candidates, &self.cursors)
+            };
+            matching.push(member);
+            chosen.push((name, member));
+        }
+        chosen
+    }
+
+    // Un membro che lascia il gruppo cede i messaggi non letti o non confermati al membro meno
+    // carico; se non ce ne sono li mantiene
+    fn leave_group(&mut self, id: u64) {
+        let RingState { entries, cursors, .. } = self;
+        let Some(cursor) = cursors.get_mut(&id) else { return };
+        let Some(group) = cursor.group.take() else { return };
+        let next = cursor.next;
+        let mut in_flight = std::mem::take(&mut cursor.in_flight);
+        let mut handed_over = std::mem::take(&mut cursor.handed_over);
+        for entry in entries.iter_mut() {
+            if entry.seq < next && !in_flight.contains_key(&entry.seq) && !handed_over.contains(&entry.seq) {
+                continue;
+            }
+            let Ok(position) = entry.recipients.binary_search(&id) else { continue };
+            let target = cursors.iter()
+                .filter(|(&other, cursor)| other != id && cursor.group.as_ref() == Some(&group) && !cursor.closed)
+                .min_by_key(|(&other, cursor)| (cursor.load(), other))
+                .map(|(&other, _)| other);
+            let Some(target) = target else { continue };
+            entry.recipients.remove(position);
+            if let Err(position) = entry.recipients.binary_search(&target) {
+                entry.recipients.insert(position, target);
+            }
+            // Chi ha già superato il messaggio lo riceve a parte, prima dei propri pendenti
+            let cursor = cursors.get_mut(&target).unwrap();
+            if cursor.next <= entry.seq {
+                cursor.pending += 1;
+            } else {
+                cursor.handed_over.insert(entry.seq);
+            }
+            if in_flight.remove(&entry.seq).is_none() && !handed_over.remove(&entry.seq) {
+                cursors.get_mut(&id).unwrap().pending -= 1;
+            }
+        }
+        let cursor = cursors.get_mut(&id).unwrap();
+        cursor.in_flight = in_flight;
+        cursor.handed_over = handed_over;
+    }
+
+    // Un messaggio resta nel buffer finché almeno un destinatario non lo ha ancora letto o finché
+    // la politica di conservazione lo richiede
+    fn trim(&mut self) {
+        while let Some(entry) = self.entries.front() {
+            let unread = entry.recipients.iter().any(|id| {
+                self.cursors.get(id).is_some_and(|cursor| {
+                    cursor.next <= entry.seq || cursor.in_flight.contains_key(&entry.seq) || cursor.handed_over.contains(&entry.seq)
+                })
+            });
+            let retained = match self.retention {
+                None => false,
+                Some(Retention::Messages(n)) => self.entries.len() <= n,
+                Some(Retention::Age(age)) => entry.published.elapsed() <= age
+            };
+            if unread || retained {
+                break;
+            }
+            self.entries.pop_front();
+        }
+    }
+
+    // Il buffer è pieno e il messaggio più vecchio non è ancora stato letto da una sottoscrizione
+    // con politica Block
+    fn ring_blocked(&self) -> bool {
+        let Some(oldest) = self.entries.front() else { return false };
+        self.capacity.is_some_and(|capacity| self.entries.len() >= capacity)
+            && oldest.recipients.iter().any(|id| {
+                self.cursors.get(id).is_some_and(|cursor| {
+                    cursor.overflow == Overflow::Block && !cursor.closed && cursor.next <= oldest.seq
+                })
+            })
+    }
+
+    // Sovrascrive i messaggi più vecchi, chi non li aveva ancora letti li perde
+    fn evict(&mut self) {
+        let Some(capacity) = self.capacity else { return };
+        while self.entries.len() >= capacity {
+            let Some(entry) = self.entries.pop_front() else { return };
+            for id in entry.recipients {
+                let Some(cursor) = self.cursors.get_mut(&id) else { continue };
+                if cursor.next <= entry.seq {
+                    cursor.pending -= 1;
+                    cursor.lagged += 1;
+                } else if cursor.in_flight.remove(&entry.seq).is_some() || cursor.handed_over.remove(&entry.seq) {
+                    cursor.lagged += 1;
+                }
+            }
+        }
+    }
+
+    // Rinuncia al messaggio non letto più vecchio destinato alla sottoscrizione id
+    fn drop_oldest(&mut self, id: u64) {
+        let Some(cursor) = self.cursors.get_mut(&id) else { return };
+        let start = self.entries.partition_point(|entry| entry.seq < cursor.next);
+        let position = self.entries.range_mut(start..).find(|entry| entry.recipients.binary_search(&id).is_ok());
+        if let Some(entry) = position {
+            entry.recipients.retain(|&recipient| recipient != id);
+            cursor.pending -= 1;
+            cursor.lagged += 1;
+        }
+    }
+}
+
+struct Shared<T: Clone + Sync + 'static> {
+    state: Mutex<RingState<T>>,
+    // Segnalata quando ci sono nuovi messaggi da leggere
+    readable: Condvar,
+    // Segnalata quando si libera spazio, per i Dispatcher in attesa con la politica Block
+    writable: Condvar
+}
+
+struct Subscription<T: Clone + Sync + 'static> {
+    id: u64,
+    // La Subscription mantiene in vita il buffer, così può leggere i messaggi pendenti anche dopo
+    // la distruzione del Dispatcher
+    shared: Arc<Shared<T>>
+}
+
+#[allow(dead_code)]
+impl <T: Clone + Sync + 'static> Subscription<T> {
+    // Restituisce None solo quando il Dispatcher è stato distrutto (o la sottoscrizione
+    // annullata) e tutti i messaggi già recapitati sono stati letti. Gli eventuali messaggi persi
+    // per una coda piena vengono ignorati, recv() permette di accorgersene
+    fn read(&self) -> Option<Msg<T>> {
+        loop {
+            match self.recv() {
+                Ok(msg) => return Some(msg),
+                Err(RecvError::Lagged(_)) => continue,
+                Err(RecvError::Closed) => return None
+            }
+        }
+    }
+
+    fn recv(&self) -> Result<Msg<T>, RecvError> {
+        let mut state = self.shared.state.lock().unwrap();
+        loop {
+            let now = Instant::now();
+            let RingState { entries, cursors, closed, .. } = &mut *state;
+            let Some(cursor) = cursors.get_mut(&self.id) else { return Err(RecvError::Closed) };
+            if cursor.lagged > 0 {
+                return Err(RecvError::Lagged(std::mem::take(&mut cursor.lagged)));
+            }
+            // Prima i messaggi non confermati entro il tempo di visibilità, dal più vecchio
+            if let Some(visibility) = cursor.visibility {
+                let expired = cursor.in_flight.iter().find(|(_, &deadline)| deadline <= now).map(|(&seq, _)| seq);
+                if let Some(seq) = expired {
+                    cursor.in_flight.insert(seq, now + visibility);
+                    // Il buffer è ordinato per numero di sequenza
+                    let position = entries.binary_search_by_key(&seq, |entry| entry.seq)
+                        .expect("un messaggio non confermato deve essere nel buffer");
+                    return Ok(entries[position].msg.clone());
+                }
+            }
+            if let Some(seq) = cursor.handed_over.pop_first() {
+                let position = entries.binary_search_by_key(&seq, |entry| entry.seq)
+                    .expect("un messaggio ceduto deve essere nel buffer");
+                let msg = entries[position].msg.clone();
+                if let Some(visibility) = cursor.visibility {
+                    cursor.in_flight.insert(seq, now + visibility);
+                }
+                state.trim();
+                self.shared.writable.notify_all();
+                return Ok(msg);
+            }
+            if cursor.pending > 0 {
+                // Si parte dalla posizione di lettura, senza scorrere i messaggi già letti
+                let start = entries.partition_point(|entry| entry.seq < cursor.next);
+                let entry = entries.range(start..)
+                    .find(|entry| entry.recipients.binary_search(&self.id).is_ok())
+                    .expect("un messaggio pendente deve essere nel buffer");
+                let msg = entry.msg.clone();
+                cursor.next = entry.seq + 1;
+                cursor.pending -= 1;
+                if let Some(visibility) = cursor.visibility {
+                    cursor.in_flight.insert(entry.seq, now + visibility);
+                }
+                state.trim();
+                self.shared.writable.notify_all();
+                return Ok(msg);
+            }
+            if (cursor.closed || *closed) && cursor.in_flight.is_empty() {
+                return Err(RecvError::Closed);
+            }
+            state = match cursor.in_flight.values().min() {
+                Some(&deadline) => self.shared.readable.wait_timeout(state, deadline.saturating_duration_since(now)).unwrap().0,
+                None => self.shared.readable.wait(state).unwrap()
+            };
+        }
+    }
+
+    // Conferma l'elaborazione di un messaggio ricevuto da una sottoscrizione creata con
+    // subscribe_acked, che quindi non verrà più consegnato. Restituisce false se il messaggio
+    // non era in attesa di conferma
+    fn ack(&self, msg: &Msg<T>) -> bool {
+        let mut state = self.shared.state.lock().unwrap();
+        let acked = state.cursors.get_mut(&self.id).is_some_and(|cursor| cursor.in_flight.remove(&msg.seq).is_some());
+        if acked {
+            state.trim();
+            // Chi attende in recv potrebbe non avere più nulla da riconsegnare
+            self.shared.readable.notify_all();
+            self.shared.writable.notify_all();
+        }
+        acked
+    }
+
+    // Distanza in numeri di sequenza tra l'ultimo messaggio pubblicato e la posizione di lettura:
+    // permette di individuare i lettori lenti
+    fn lag(&self) -> u64 {
+        let state = self.shared.state.lock().unwrap();
+        state.cursors.get(&self.id).map_or(0, |cursor| state.next_seq.saturating_sub(cursor.next))
+    }
+
+    fn iter(&self) -> Messages<'_, T> {
+        self.into_iter()
+    }
+
+    // Il Dispatcher smette di recapitare messaggi a questa sottoscrizione, quelli già in coda
+    // possono ancora essere letti
+    fn unsubscribe(&self) {
+        let mut state = self.shared.state.lock().unwrap();
+        state.leave_group(self.id);
+        let RingState { cursors, topics, .. } = &mut *state;
+        if let Some(cursor) = cursors.get_mut(&self.id) {
+            cursor.closed = true;
+            topics.remove(&cursor.pattern, self.id);
+        }
+        self.shared.readable.notify_all();
+        self.shared.writable.notify_all();
+    }
+}
+
+impl <T: Clone + Sync + 'static> Drop for Subscription<T> {
+    fn drop(&mut self) {
+        let mut state = self.shared.state.lock().unwrap();
+        state.remove(self.id);
+        state.trim();
+        // Un Dispatcher bloccato su questa sottoscrizione deve poter proseguire e gli altri membri
+        // del gruppo possono aver ricevuto i messaggi non letti
+        self.shared.writable.notify_all();
+        self.shared.readable.notify_all();
+    }
+}
+
+// La Subscription è un iteratore che termina quando read() restituisce None, quindi i messaggi
+// possono essere elaborati con map, filter, take_while...
+impl <T: Clone + Sync + 'static> Iterator for Subscription<T> {
+    type Item = Msg<T>;
+
+    fn next(&mut self) -> Option<Msg<T>> {
+        self.read()
+    }
+}
+
+// Per iterare senza consumare la Subscription, ad esempio per poterla poi annullare
+struct Messages<'a, T: Clone + Sync + 'static> {
+    subscription: &'a Subscription<T>
+}
+
+impl <T: Clone + Sync + 'static> Iterator for Messages<'_, T> {
+    type Item = Msg<T>;
+
+    fn next(&mut self) -> Option<Msg<T>> {
+        self.subscription.read()
+    }
+}
+
+impl <'a, T: Clone + Sync + 'static> IntoIterator for &'a Subscription<T> {
+    type Item = Msg<T>;
+    type IntoIter = Messages<'a, T>;
+
+    fn into_iter(self) -> Messages<'a, T> {
+        Messages { subscription: self }
+    }
+}
+
+struct Dispatcher<T: Clone + Sync + 'static> {
+    shared: Arc<Shared<T>>
+}
+
+#[allow(dead_code)]
+impl <T: Clone + Sync + 'static> Dispatcher<T> {
+    fn new() -> Self {
+        Dispatcher::build(None, None)
+    }
+
+    // Il buffer condiviso contiene al più capacity messaggi: quando è pieno il più vecchio viene
+    // sovrascritto e chi non lo aveva ancora letto riceve Lagged (o, con la politica Block, il
+    // Dispatcher attende che venga letto)
+    fn with_capacity(capacity: usize) -> Self {
+        assert!(capacity > 0, "la capacità deve essere positiva");
+        Dispatcher::build(Some(capacity), None)
+    }
+
+    // I messaggi vengono conservati secondo retention, così chi si sottoscrive in ritardo con
+    // subscribe_from può recuperarli
+    fn with_retention(retention: Retention) -> Self {
+        Dispatcher::build(None, Some(retention))
+    }
+
+    // I messaggi vengono scritti nel log su disco descritto da config prima di essere recapitati.
+    // Alla riapertura il numero di sequenza riprende dall'ultimo messaggio scritto e quelli ancora
+    // conservati secondo retention sono disponibili per subscribe_from. Un messaggio del log che
+    // non può essere decodificato come T è un errore InvalidData
+    fn persistent(config: LogConfig, retention: Option<Retention>) -> io::Result<Self>
+    where T: Codec {
+        let (log, records) = SegmentLog::open(config)?;
+        let dispatcher = Dispatcher::build(None, retention);
+        {
+            let mut state = dispatcher.shared.state.lock().unwrap();
+            state.next_seq = log.next_seq();
+            for record in records {
+                let Some(message) = T::decode(&record.payload) else {
+                    return Err(io::Error::new(io::ErrorKind::InvalidData, format!(
+                        "il messaggio {} non può essere decodificato", record.seq
+                    )));
+                };
+                let age = SystemTime::now().duration_since(record.published).unwrap_or_default();
+                state.entries.push_back(Entry {
+                    seq: record.seq,
+                    msg: Msg {
+                        seq: record.seq,
+                        topic: record.topic.into(),
+                        ..Msg::new(message)
+                    },
+                    recipients: vec![],
+                    published: Instant::now().checked_sub(age).unwrap_or_else(Instant::now)
+                });
+            }
+            state.persistence = Some(Persistence {
+                log,
+                encode: T::encode
+            });
+            state.trim();
+        }
+        Ok(dispatcher)
+    }
+
+    fn build(capacity: Option<usize>, retention: Option<Retention>) -> Self {
+        Dispatcher {
+            shared: Arc::new(Shared {
+                state: Mutex::new(RingState {
+                    entries: VecDeque::new(),
+                    next_seq: 0,
+                    capacity,
+                    retention,
+                    persistence: None,
+                    cursors: HashMap::new(),
+                    topics: TopicNode::default(),
+                    groups: HashMap::new(),
+                    next_id: 0,
+                    next_correlation_id: 0,
+                    closed: false
+                }),
+                readable: Condvar::new(),
+                writable: Condvar::new()
+            })
+        }
+    }
+
+    // Pubblica sul topic vuoto, che raggiunge solo le sottoscrizioni a "#" come quelle create da
+    // subscribe()
+    fn dispatch(&self, msg: Msg<T>) -> io::Result<usize> {
+        self.dispatch_topic("", msg)
+    }
+
+    // Restituisce il numero di sottoscrizioni a cui il messaggio è stato recapitato. Il messaggio
+    // viene scritto una sola volta, indipendentemente dal numero di destinatari. Può fallire solo
+    // con un Dispatcher persistent: se la scrittura del log fallisce il messaggio non viene
+    // recapitato
+    fn dispatch_topic(&self, topic: &str, mut msg: Msg<T>) -> io::Result<usize> {
+        msg.topic = topic.into();
+        let levels: Vec<&str> = topic_levels(topic).collect();
+        let mut state = self.shared.state.lock().unwrap();
+        let matching = loop {
+            let mut matching = vec![];
+            state.topics.matches(&levels, &mut matching);
+            matching.retain(|id| {
+                let cursor = &state.cursors[id];
+                !cursor.closed && cursor.filter.as_ref().is_none_or(|filter| filter(&msg.message))
+            });
+            let chosen = state.pick_group_members(&mut matching);
+            matching.sort_unstable();
+            let blocked = state.ring_blocked() || matching.iter().any(|id| {
+                let cursor = &state.cursors[id];
+                cursor.overflow == Overflow::Block && cursor.is_full()
+            });
+            if !blocked {
+                for (name, member) in chosen {
+                    state.groups.entry(name).or_default().last = Some(member);
+                }
+                break matching;
+            }
+            // L'attesa rilascia il lock: nel frattempo le sottoscrizioni possono essere create,
+            // lette o distrutte, quindi al risveglio i destinatari vengono individuati di nuovo
+            state = self.shared.writable.wait(state).unwrap();
+        };
+        let seq = state.next_seq;
+        msg.seq = seq;
+        let published = SystemTime::now();
+        if let Some(persistence) = state.persistence.as_mut() {
+            persistence.append(&msg, published)?;
+        }
+        state.next_seq += 1;
+        // Solo un messaggio effettivamente scritto può prendere il posto dei più vecchi
+        state.evict();
+
+        let mut recipients = Vec::with_capacity(matching.len());
+        for id in matching {
+            // Una sottoscrizione con Offset::At nel futuro non riceve i messaggi precedenti
+            let Some(cursor) = state.cursors.get_mut(&id).filter(|cursor| !cursor.closed && cursor.next <= seq) else { continue };
+            if cursor.is_full() {
+                match cursor.overflow {
+                    Overflow::Block => {},
+                    Overflow::DropNewest => {
+                        cursor.lagged += 1;
+                        continue;
+                    },
+                    Overflow::DropOldest => state.drop_oldest(id),
+                    Overflow::Disconnect => {
+                        cursor.lagged += 1;
+                        cursor.closed = true;
+                        continue;
+                    }
+                }
+            }
+            if let Some(cursor) = state.cursors.get_mut(&id) {
+                cursor.pending += 1;
+                recipients.push(id);
+            }
+        }
+
+        let delivered = recipients.len();
+        if delivered > 0 || state.retention.is_some() {
+            state.entries.push_back(Entry { seq, msg, recipients, published: Instant::now() });
+        }
+        state.trim();
+        self.shared.readable.notify_all();
+        Ok(delivered)
+    }
+
+    // Pubblica msg come richiesta e attende la prima risposta inviata con Msg::reply da uno dei
+    // destinatari
+    fn request(&self, topic: &str, mut msg: Msg<T>, timeout: Duration) -> Result<T, RequestError> {
+        let slot = Arc::new(ReplySlot::new());
+        msg.reply_to = Some(Arc::clone(&slot));
+        msg.correlation_id = Some({
+            let mut state = self.shared.state.lock().unwrap();
+            state.next_correlation_id += 1;
+            state.next_correlation_id
+        });
+        if self.dispatch_topic(topic, msg).map_err(RequestError::Io)? == 0 {
+            return Err(RequestError::NoSubscribers);
+        }
+        slot.wait(timeout).ok_or(RequestError::Timeout)
+    }
+
+    // Riceve tutti i messaggi, indipendentemente dal topic
+    fn subscribe(&self) -> Subscription<T> {
+        self.subscribe_topic("#").unwrap()
+    }
+
+    // Senza limite di messaggi pendenti: se il buffer del Dispatcher è limitato, i messaggi non
+    // letti in tempo vengono sovrascritti
+    fn subscribe_topic(&self, pattern: &str) -> Result<Subscription<T>, TopicError> {
+        self.subscribe_filtered(pattern, SubscribeOptions::default())
+    }
+
+    // Riceve anche i messaggi conservati a partire da offset
+    fn subscribe_from(&self, offset: Offset) -> Subscription<T> {
+        self.subscribe_topic_from("#", offset).unwrap()
+    }
+
+    fn subscribe_topic_from(&self, pattern: &str, offset: Offset) -> Result<Subscription<T>, TopicError> {
+        self.subscribe_filtered(pattern, SubscribeOptions { offset, ..SubscribeOptions::default() })
+    }
+
+    // Al più capacity messaggi possono essere pendenti per la sottoscrizione, oltre si applica
+    // overflow
+    fn subscribe_bounded(&self, pattern: &str, capacity: usize, overflow: Overflow) -> Result<Subscription<T>, TopicError> {
+        self.subscribe_filtered(pattern, SubscribeOptions { capacity: Some(capacity), overflow, ..SubscribeOptions::default() })
+    }
+
+    // Riceve solo i messaggi per cui filter restituisce true, il predicato viene valutato dal
+    // Dispatcher
+    fn subscribe_with(&self, filter: impl Fn(&T) -> bool + Send + 'static) -> Subscription<T> {
+        self.subscribe_topic_with("#", filter).unwrap()
+    }
+
+    fn subscribe_topic_with(&self, pattern: &str, filter: impl Fn(&T) -> bool + Send + 'static) -> Result<Subscription<T>, TopicError> {
+        self.subscribe_filtered(pattern, SubscribeOptions { filter: Some(Box::new(filter)), ..SubscribeOptions::default() })
+    }
+
+    // Consegna almeno una volta: ogni messaggio letto va confermato con Subscription::ack entro
+    // visibility, altrimenti viene consegnato di nuovo
+    fn subscribe_acked(&self, pattern: &str, visibility: Duration) -> Result<Subscription<T>, TopicError> {
+        self.subscribe_filtered(pattern, SubscribeOptions { visibility: Some(visibility), ..SubscribeOptions::default() })
+    }
+
+    // Membro del gruppo name: ogni messaggio viene recapitato ad un solo membro del gruppo, oltre
+    // che a tutte le altre sottoscrizioni interessate
+    fn subscribe_group(&self, name: &str) -> Subscription<T> {
+        self.subscribe_group_topic(name, "#").unwrap()
+    }
+
+    fn subscribe_group_topic(&self, name: &str, pattern: &str) -> Result<Subscription<T>, TopicError> {
+        self.subscribe_filtered(pattern, SubscribeOptions { group: Some(name.into()), ..SubscribeOptions::default() })
+    }
+
+    // Cambia il criterio con cui vengono scelti i membri del gruppo name, anche prima che il
+    // gruppo abbia dei membri
+    fn set_group_balance(&self, name: &str, balance: Balance) {
+        let mut state = self.shared.state.lock().unwrap();
+        state.groups.entry(name.into()).or_default().balance = balance;
+    }
+
+    fn subscribe_filtered(&self, pattern: &str, options: SubscribeOptions<T>) -> Result<Subscription<T>, TopicError> {
+        let SubscribeOptions { filter, capacity, overflow, offset, visibility, group } = options;
+        assert!(capacity != Some(0), "la capacità deve essere positiva");
+        let pattern = parse_pattern(pattern)?;
+        let mut state = self.shared.state.lock().unwrap();
+        let id = state.next_id;
+        state.next_id += 1;
+        state.topics.insert(&pattern, id);
+
+        let first = state.first_seq();
+        let next = match offset {
+            Offset::Earliest => first,
+            Offset::Latest => state.next_seq,
+            Offset::At(seq) => seq.max(first)
+        };
+        // Se i messaggi richiesti non sono più conservati il destinatario lo scopre con Lagged
+        let lagged = match offset {
+            Offset::At(seq) => first.saturating_sub(seq),
+            _ => 0
+        };
+
+        // I messaggi conservati vengono assegnati alla nuova sottoscrizione come se fossero stati
+        // pubblicati ora
+        let mut pending = 0;
+        let start = state.entries.partition_point(|entry| entry.seq < next);
+        for entry in state.entries.range_mut(start..) {
+            let topic: Vec<&str> = topic_levels(&entry.msg.topic).collect();
+            let accepted = filter.as_ref().is_none_or(|filter| filter(&entry.msg.message));
+            if accepted && pattern_matches(&pattern, &topic) {
+                if let Err(position) = entry.recipients.binary_search(&id) {
+                    entry.recipients.insert(position, id);
+                }
+                pending += 1;
+            }
+        }
+
+        state.cursors.insert(id, Cursor {
+            pattern,
+            filter,
+            next,
+            pending,
+            lagged,
+            capacity,
+            overflow,
+            visibility,
+            in_flight: BTreeMap::new(),
+            handed_over: BTreeSet::new(),
+            group,
+            closed: false
+        });
+        Ok(Subscription {
+            id,
+            shared: Arc::clone(&self.shared)
+        })
+    }
+}
+
+// I destinatari, letto quanto già recapitato, ricevono None
+impl <T: Clone + Sync + 'static> Drop for Dispatcher<T> {
+    fn drop(&mut self) {
+        self.shared.state.lock().unwrap().closed = true;
+        self.shared.readable.notify_all();
+    }
+}
+
+use std::thread::spawn;
+
+
+pub fn main() {
+    let dispatcher = Dispatcher::new();
+    let mut receivers_handles = vec![];
+
+    for i in 0..5 {
+        let sub = dispatcher.subscribe();
+        let handle = spawn(move || { 
+            println!("Started receiver {}",i);
+            for msg in sub {
+                println!("{}",msg.message);
+            }
+            println!("Stopped receiver {}",i);
+        });
+        receivers_handles.push(handle);
+    }
+
+    let msg = Msg::new("Hi from the main thread!".to_string());
+    println!("Started main sender");
+    dispatcher.dispatch(msg).unwrap();
+    // Distruggendo il Dispatcher i receiver terminano dopo aver letto i messaggi in coda
+    drop(dispatcher);
+
+    for handle in receivers_handles {
+        handle.join().unwrap();
+    }
+
+    // Lo stesso Dispatcher usato da un altro processo tramite socket
+    let dispatcher = Arc::new(Dispatcher::<String>::new());
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    net::serve(Arc::clone(&dispatcher), listener);
+    let subscriber = net::Client::connect_tcp(addr).unwrap();
+    subscriber.subscribe("greetings.*").unwrap();
+    let publisher = net::Client::connect_tcp(addr).unwrap();
+    publisher.publish("greetings.tcp", &"Hi over TCP!".to_string()).unwrap();
+    #[cfg(unix)]
+    {
+        let path = std::env::temp_dir().join(format!("tema_08092022_{}.sock", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        net::serve(Arc::clone(&dispatcher), std::os::unix::net::UnixListener::bind(&path).unwrap());
+        net::Client::connect_unix(&path).unwrap().publish("greetings.unix", &"Hi over a Unix socket!".to_string()).unwrap();
+        println!("{}", subscriber.recv::<String>().unwrap().unwrap().message);
+        let _ = std::fs::remove_file(&path);
+    }
+    println!("{}", subscriber.recv::<String>().unwrap().unwrap().message);
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{Balance, Dispatcher, Msg, Offset, Overflow, Persistence, RequestError, Retention, RecvError, TopicError};
+    use crate::storage::{Codec, LogConfig, SegmentLog};
+    use std::{fs, io, sync::Arc, thread::{sleep, spawn}, time::Duration};
+
+    fn msg(message: u32) -> Msg<u32> {
+        Msg::new(message)
+    }
+
+    #[test]
+    fn dropped_subscription_does_not_stop_others() {
+        let dispatcher = Dispatcher::new();
+        let first = dispatcher.subscribe();
+        let second = dispatcher.subscribe();
+        assert_eq!(dispatcher.dispatch(msg(1)).unwrap(), 2);
+        drop(first);
+        assert_eq!(dispatcher.dispatch(msg(2)).unwrap(), 1);
+        assert_eq!(second.read().map(|m| *m.message), Some(1));
+        assert_eq!(second.read().map(|m| *m.message), Some(2));
+    }
+
+    #[test]
+    fn read_drains_after_dispatcher_drop() {
+        let dispatcher = Dispatcher::new();
+        let sub = dispatcher.subscribe();
+        dispatcher.dispatch(msg(1)).unwrap();
+        dispatcher.dispatch(msg(2)).unwrap();
+        drop(dispatcher);
+        assert_eq!(sub.read().map(|m| *m.message), Some(1));
+        assert_eq!(sub.read().map(|m| *m.message), Some(2));
+        assert!(sub.read().is_none());
+    }
+
+    #[test]
+    fn read_blocks_until_dispatch() {
+        let dispatcher = Dispatcher::new();
+        let sub = dispatcher.subscribe();
+        let handle = spawn(move || sub.read().map(|m| *m.message));
+        dispatcher.dispatch(msg(7)).unwrap();
+        assert_eq!(handle.join().unwrap(), Some(7));
+    }
+
+    #[test]
+    fn unsubscribe_stops_delivery() {
+        let dispatcher = Dispatcher::new();
+        let sub = dispatcher.subscribe();
+        dispatcher.dispatch(msg(1)).unwrap();
+        sub.unsubscribe();
+        assert_eq!(dispatcher.dispatch(msg(2)).unwrap(), 0);
+        assert_eq!(sub.read().map(|m| *m.message), Some(1));
+        assert!(sub.read().is_none());
+    }
+
+    #[test]
+    fn topic_wildcards() {
+        let dispatcher = Dispatcher::new();
+        let exact = dispatcher.subscribe_topic("orders.created").unwrap();
+        let single = dispatcher.subscribe_topic("orders.*").unwrap();
+        let multi = dispatcher.subscribe_topic("orders.#").unwrap();
+        let all = dispatcher.subscribe();
+
+        assert_eq!(dispatcher.dispatch_topic("orders.created", msg(1)).unwrap(), 4);
+        assert_eq!(dispatcher.dispatch_topic("orders.eu.created", msg(2)).unwrap(), 2);
+        assert_eq!(dispatcher.dispatch_topic("orders", msg(3)).unwrap(), 2);
+        assert_eq!(dispatcher.dispatch_topic("payments.created", msg(4)).unwrap(), 1);
+        assert_eq!(dispatcher.dispatch(msg(5)).unwrap(), 1);
+        drop(dispatcher);
+
+        let read_all = |sub: crate::Subscription<u32>| {
+            let mut messages = vec![];
+            while let Some(m) = sub.read() {
+                messages.push((m.topic.to_string(), *m.message));
+            }
+            messages
+        };
+        assert_eq!(read_all(exact), vec![("orders.created".to_string(), 1)]);
+        assert_eq!(read_all(single).len(), 1);
+        assert_eq!(read_all(multi).iter().map(|m| m.1).collect::<Vec<_>>(), vec![1, 2, 3]);
+        assert_eq!(read_all(all).iter().map(|m| m.1).collect::<Vec<_>>(), vec![1, 2, 3, 4, 5]);
+    }
+
+    #[test]
+    fn invalid_patterns() {
+        let dispatcher = Dispatcher::<u32>::new();
+        assert_eq!(dispatcher.subscribe_topic("orders..created").err(), Some(TopicError::EmptyLevel));
+        assert_eq!(dispatcher.subscribe_topic("#.created").err(), Some(TopicError::MisplacedWildcard));
+        assert_eq!(dispatcher.subscribe_topic("orders.cr*").err(), Some(TopicError::PartialWildcard));
+    }
+
+    #[test]
+    fn unsubscribe_removes_topic() {
+        let dispatcher = Dispatcher::new();
+        let sub = dispatcher.subscribe_topic("orders.*").unwrap();
+        sub.unsubscribe();
+        assert_eq!(dispatcher.dispatch_topic("orders.created", msg(1)).unwrap(), 0);
+        assert!(dispatcher.shared.state.lock().unwrap().topics.children.is_empty());
+    }
+
+    #[test]
+    fn filter_is_evaluated_on_dispatch() {
+        let dispatcher = Dispatcher::new();
+        let even = dispatcher.subscribe_with(|n: &u32| n.is_multiple_of(2));
+        let orders = dispatcher.subscribe_topic_with("orders.*", |n: &u32| *n > 10).unwrap();
+        let delivered: usize = (0..5).map(|n| dispatcher.dispatch(msg(n)).unwrap()).sum();
+        assert_eq!(delivered, 3);
+        assert_eq!(dispatcher.dispatch_topic("orders.created", msg(5)).unwrap(), 0);
+        assert_eq!(dispatcher.dispatch_topic("orders.created", msg(20)).unwrap(), 2);
+        drop(dispatcher);
+
+        assert_eq!(even.map(|m| *m.message).collect::<Vec<_>>(), vec![0, 2, 4, 20]);
+        assert_eq!(orders.map(|m| *m.message).collect::<Vec<_>>(), vec![20]);
+    }
+
+    #[test]
+    fn iterator_combinators() {
+        let dispatcher = Dispatcher::new();
+        let sub = dispatcher.subscribe();
+        for n in 0..10 {
+            dispatcher.dispatch(msg(n)).unwrap();
+        }
+        let firsts: Vec<u32> = sub.iter()
+            .map(|m| *m.message)
+            .take_while(|&n| n < 4)
+            .filter(|n| !n.is_multiple_of(2))
+            .collect();
+        assert_eq!(firsts, vec![1, 3]);
+        // take_while ha consumato anche il primo messaggio non valido
+        sub.unsubscribe();
+        assert_eq!((&sub).into_iter().count(), 5);
+    }
+
+    #[test]
+    fn bounded_drop_newest() {
+        let dispatcher = Dispatcher::new();
+        let sub = dispatcher.subscribe_bounded("#", 2, Overflow::DropNewest).unwrap();
+        let delivered: usize = (0..5).map(|n| dispatcher.dispatch(msg(n)).unwrap()).sum();
+        assert_eq!(delivered, 2);
+        assert_eq!(sub.recv().map(|m| *m.message), Err(RecvError::Lagged(3)));
+        assert_eq!(sub.recv().map(|m| *m.message), Ok(0));
+        assert_eq!(sub.recv().map(|m| *m.message), Ok(1));
+        drop(dispatcher);
+        assert_eq!(sub.recv().map(|m| *m.message), Err(RecvError::Closed));
+    }
+
+    #[test]
+    fn bounded_drop_oldest() {
+        let dispatcher = Dispatcher::new();
+        let sub = dispatcher.subscribe_bounded("#", 2, Overflow::DropOldest).unwrap();
+        for n in 0..5 {
+            dispatcher.dispatch(msg(n)).unwrap();
+        }
+        drop(dispatcher);
+        assert_eq!(sub.recv().map(|m| *m.message), Err(RecvError::Lagged(3)));
+        assert_eq!(sub.map(|m| *m.message).collect::<Vec<_>>(), vec![3, 4]);
+    }
+
+    #[test]
+    fn bounded_disconnect() {
+        let dispatcher = Dispatcher::new();
+        let slow = dispatcher.subscribe_bounded("#", 1, Overflow::Disconnect).unwrap();
+        let other = dispatcher.subscribe();
+        assert_eq!(dispatcher.dispatch(msg(1)).unwrap(), 2);
+        assert_eq!(dispatcher.dispatch(msg(2)).unwrap(), 1);
+        assert_eq!(dispatcher.dispatch(msg(3)).unwrap(), 1);
+        assert_eq!(slow.recv().map(|m| *m.message), Err(RecvError::Lagged(1)));
+        assert_eq!(slow.recv().map(|m| *m.message), Ok(1));
+        assert_eq!(slow.recv().map(|m| *m.message), Err(RecvError::Closed));
+        drop(dispatcher);
+        assert_eq!(other.count(), 3);
+    }
+
+    #[test]
+    fn bounded_block() {
+        let dispatcher = Dispatcher::new();
+        let sub = dispatcher.subscribe_bounded("#", 1, Overflow::Block).unwrap();
+        let reader = spawn(move || {
+            sleep(Duration::from_millis(20));
+            sub.map(|m| *m.message).collect::<Vec<_>>()
+        });
+        for n in 0..5 {
+            assert_eq!(dispatcher.dispatch(msg(n)).unwrap(), 1);
+        }
+        drop(dispatcher);
+        assert_eq!(reader.join().unwrap(), vec![0, 1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn blocked_dispatch_resumes_when_subscription_dropped() {
+        let dispatcher = Dispatcher::new();
+        let sub = dispatcher.subscribe_bounded("#", 1, Overflow::Block).unwrap();
+        dispatcher.dispatch(msg(1)).unwrap();
+        let dropper = spawn(move || {
+            sleep(Duration::from_millis(20));
+            drop(sub);
+        });
+        assert_eq!(dispatcher.dispatch(msg(2)).unwrap(), 0);
+        dropper.join().unwrap();
+    }
+
+    #[test]
+    fn single_write_shared_by_subscribers() {
+        let dispatcher = Dispatcher::new();
+        let subs: Vec<_> = (0..3).map(|_| dispatcher.subscribe()).collect();
+        assert_eq!(dispatcher.dispatch(msg(1)).unwrap(), 3);
+        assert_eq!(dispatcher.shared.state.lock().unwrap().entries.len(), 1);
+
+        let received: Vec<_> = subs.iter().map(|sub| sub.read().unwrap()).collect();
+        assert!(received.windows(2).all(|pair| Arc::ptr_eq(&pair[0].message, &pair[1].message)));
+        // Letto da tutti, il messaggio non occupa più il buffer
+        assert!(dispatcher.shared.state.lock().unwrap().entries.is_empty());
+    }
+
+    #[test]
+    fn ring_overwrites_slow_reader() {
+        let dispatcher = Dispatcher::with_capacity(3);
+        let slow = dispatcher.subscribe();
+        let fast = dispatcher.subscribe();
+        for n in 0..5 {
+            dispatcher.dispatch(msg(n)).unwrap();
+            assert_eq!(fast.read().map(|m| *m.message), Some(n));
+        }
+        assert_eq!(slow.lag(), 5);
+        assert_eq!(slow.recv().map(|m| *m.message), Err(RecvError::Lagged(2)));
+        assert_eq!(slow.recv().map(|m| *m.message), Ok(2));
+        assert_eq!(slow.lag(), 2);
+    }
+
+    #[test]
+    fn ring_blocks_for_block_subscribers() {
+        let dispatcher = Dispatcher::with_capacity(2);
+        let sub = dispatcher.subscribe_bounded("#", 10, Overflow::Block).unwrap();
+        let reader = spawn(move || {
+            sleep(Duration::from_millis(20));
+            sub.map(|m| *m.message).collect::<Vec<_>>()
+        });
+        for n in 0..6 {
+            dispatcher.dispatch(msg(n)).unwrap();
+            assert!(dispatcher.shared.state.lock().unwrap().entries.len() <= 2);
+        }
+        drop(dispatcher);
+        assert_eq!(reader.join().unwrap(), vec![0, 1, 2, 3, 4, 5]);
+    }
+
+    #[test]
+    fn late_subscriber_replays_retained() {
+        let dispatcher = Dispatcher::with_retention(Retention::Messages(3));
+        for n in 0..5 {
+            assert_eq!(dispatcher.dispatch_topic(if n % 2 == 0 { "even" } else { "odd" }, msg(n)).unwrap(), 0);
+        }
+        let earliest = dispatcher.subscribe_from(Offset::Earliest);
+        let odd = dispatcher.subscribe_topic_from("odd", Offset::Earliest).unwrap();
+        let latest = dispatcher.subscribe_from(Offset::Latest);
+        dispatcher.dispatch_topic("odd", msg(5)).unwrap();
+        drop(dispatcher);
+
+        assert_eq!(earliest.map(|m| (m.seq, *m.message)).collect::<Vec<_>>(), vec![(2, 2), (3, 3), (4, 4), (5, 5)]);
+        assert_eq!(odd.map(|m| *m.message).collect::<Vec<_>>(), vec![3, 5]);
+        assert_eq!(latest.map(|m| *m.message).collect::<Vec<_>>(), vec![5]);
+    }
+
+    #[test]
+    fn resume_without_gaps_or_duplicates() {
+        let dispatcher = Dispatcher::with_retention(Retention::Messages(100));
+        let sub = dispatcher.subscribe();
+        for n in 0..3 {
+            dispatcher.dispatch(msg(n)).unwrap();
+        }
+        let last = sub.read().unwrap().seq;
+        drop(sub);
+        for n in 3..5 {
+            dispatcher.dispatch(msg(n)).unwrap();
+        }
+        let resumed = dispatcher.subscribe_from(Offset::At(last + 1));
+        drop(dispatcher);
+        assert_eq!(resumed.map(|m| m.seq).collect::<Vec<_>>(), vec![1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn resume_from_expired_offset_reports_lag() {
+        let dispatcher = Dispatcher::with_retention(Retention::Age(Duration::from_millis(20)));
+        dispatcher.dispatch(msg(0)).unwrap();
+        sleep(Duration::from_millis(30));
+        dispatcher.dispatch(msg(1)).unwrap();
+        let sub = dispatcher.subscribe_from(Offset::At(0));
+        assert_eq!(sub.recv().map(|m| *m.message), Err(RecvError::Lagged(1)));
+        assert_eq!(sub.recv().map(|m| *m.message), Ok(1));
+    }
+
+    #[test]
+    fn persistent_dispatcher_recovers_after_restart() {
+        let dir = std::env::temp_dir().join(format!("tema_08092022_dispatcher_{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        {
+            let dispatcher = Dispatcher::persistent(LogConfig::new(&dir), None).unwrap();
+            let sub = dispatcher.subscribe();
+            for n in 0..3 {
+                dispatcher.dispatch_topic("audit", Msg::new(format!("event {}", n))).unwrap();
+            }
+            assert_eq!(sub.read().map(|m| m.seq), Some(0));
+        }
+
+        let dispatcher = Dispatcher::<String>::persistent(LogConfig::new(&dir), Some(Retention::Messages(2))).unwrap();
+        let replay = dispatcher.subscribe_from(Offset::Earliest);
+        dispatcher.dispatch_topic("audit", Msg::new("event 3".to_string())).unwrap();
+        drop(dispatcher);
+        let replayed: Vec<_> = replay.map(|m| (m.seq, m.topic.to_string(), m.message.to_string())).collect();
+        assert_eq!(replayed, vec![
+            (1, "audit".to_string(), "event 1".to_string()),
+            (2, "audit".to_string(), "event 2".to_string()),
+            (3, "audit".to_string(), "event 3".to_string())
+        ]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn persistent_dispatcher_rejects_undecodable_records() {
+        let dir = std::env::temp_dir().join(format!("tema_08092022_undecodable_{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        Dispatcher::persistent(LogConfig::new(&dir), None).unwrap()
+            .dispatch(Msg::new("not a number".to_string())).unwrap();
+        let error = Dispatcher::<u32>::persistent(LogConfig::new(&dir), None).err().unwrap();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn failed_write_does_not_evict() {
+        let dir = std::env::temp_dir().join(format!("tema_08092022_failed_write_{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let dispatcher = Dispatcher::with_capacity(2);
+        let sub = dispatcher.subscribe();
+        {
+            // Ogni scrittura dopo la prima passa ad un nuovo segmento
+            let log = SegmentLog::open(LogConfig { segment_bytes: 1, ..LogConfig::new(&dir) }).unwrap().0;
+            dispatcher.shared.state.lock().unwrap().persistence = Some(Persistence { log, encode: <u32 as Codec>::encode });
+        }
+        dispatcher.dispatch(msg(0)).unwrap();
+        dispatcher.dispatch(msg(1)).unwrap();
+        // Senza la directory il nuovo segmento non può essere creato
+        fs::remove_dir_all(&dir).unwrap();
+        assert!(dispatcher.dispatch(msg(2)).is_err());
+        drop(dispatcher);
+        assert_eq!(sub.map(|m| *m.message).collect::<Vec<_>>(), vec![0, 1]);
+    }
+
+    #[test]
+    fn request_reply() {
+        let dispatcher = Dispatcher::new();
+        assert!(matches!(dispatcher.request("sum", msg(1), Duration::from_millis(10)), Err(RequestError::NoSubscribers)));
+
+        let server = dispatcher.subscribe_topic("sum").unwrap();
+        let handle = spawn(move || {
+            for request in server.iter().take(2) {
+                assert!(request.correlation_id.is_some());
+                assert!(request.reply(*request.message + 1));
+                assert!(!request.reply(0));
+            }
+        });
+        assert_eq!(dispatcher.request("sum", msg(1), Duration::from_secs(1)).unwrap(), 2);
+        assert_eq!(dispatcher.request("sum", msg(41), Duration::from_secs(1)).unwrap(), 42);
+        handle.join().unwrap();
+
+        let _silent = dispatcher.subscribe_topic("sum").unwrap();
+        assert!(matches!(dispatcher.request("sum", msg(1), Duration::from_millis(10)), Err(RequestError::Timeout)));
+        assert!(!msg(1).reply(2));
+    }
+
+    #[test]
+    fn unacked_messages_are_redelivered() {
+        let dispatcher = Dispatcher::new();
+        let sub = dispatcher.subscribe_acked("#", Duration::from_millis(20)).unwrap();
+        dispatcher.dispatch(msg(1)).unwrap();
+        dispatcher.dispatch(msg(2)).unwrap();
+
+        let first = sub.read().unwrap();
+        let second = sub.read().unwrap();
+        assert!(sub.ack(&second));
+        assert!(!sub.ack(&second));
+        // Il primo non è stato confermato: dopo il tempo di visibilità viene riconsegnato
+        let redelivered = sub.read().unwrap();
+        assert_eq!((redelivered.seq, *redelivered.message), (first.seq, 1));
+        assert!(sub.ack(&redelivered));
+
+        drop(dispatcher);
+        assert!(sub.read().is_none());
+    }
+
+    #[test]
+    fn blocked_dispatch_recomputes_recipients() {
+        let dispatcher = Arc::new(Dispatcher::new());
+        let blocking = dispatcher.subscribe_bounded("#", 1, Overflow::Block).unwrap();
+        let first = dispatcher.subscribe_group("workers");
+        dispatcher.dispatch(msg(0)).unwrap();
+        let dispatcher_clone = Arc::clone(&dispatcher);
+        let handle = spawn(move || dispatcher_clone.dispatch(msg(1)).unwrap());
+        sleep(Duration::from_millis(20));
+        // Mentre il dispatch è bloccato arriva un nuovo sottoscrittore e il membro del gruppo
+        // scelto per il messaggio se ne va
+        let late = dispatcher.subscribe();
+        let second = dispatcher.subscribe_group("workers");
+        assert_eq!(*first.read().unwrap().message, 0);
+        drop(first);
+        assert_eq!(*blocking.read().unwrap().message, 0);
+        assert_eq!(handle.join().unwrap(), 3);
+        assert_eq!(*late.read().unwrap().message, 1);
+        assert_eq!(*second.read().unwrap().message, 1);
+    }
+
+    #[test]
+    fn group_members_share_messages() {
+        let dispatcher = Dispatcher::new();
+        let broadcast = dispatcher.subscribe();
+        let first = dispatcher.subscribe_group("workers");
+        let second = dispatcher.subscribe_group("workers");
+        for i in 0..4 {
+            // Un solo membro del gruppo più la sottoscrizione normale
+            assert_eq!(dispatcher.dispatch(msg(i)).unwrap(), 2);
+        }
+        drop(dispatcher);
+        assert_eq!(broadcast.map(|m| *m.message).collect::<Vec<_>>(), vec![0, 1, 2, 3]);
+        assert_eq!(first.map(|m| *m.message).collect::<Vec<_>>(), vec![0, 2]);
+        assert_eq!(second.map(|m| *m.message).collect::<Vec<_>>(), vec![1, 3]);
+    }
+
+    #[test]
+    fn least_loaded_group() {
+        let dispatcher = Dispatcher::new();
+        dispatcher.set_group_balance("workers", Balance::LeastLoaded);
+        let idle = dispatcher.subscribe_group("workers");
+        let busy = dispatcher.subscribe_group("workers");
+        dispatcher.dispatch(msg(0)).unwrap();
+        dispatcher.dispatch(msg(1)).unwrap();
+        assert_eq!(*busy.read().unwrap().message, 1);
+        // idle ha ancora un messaggio da leggere, busy nessuno
+        dispatcher.dispatch(msg(2)).unwrap();
+        assert_eq!(*busy.read().unwrap().message, 2);
+        assert_eq!(*idle.read().unwrap().message, 0);
+    }
+
+    #[test]
+    fn leaving_member_hands_over_pending() {
+        let dispatcher = Dispatcher::new();
+        let first = dispatcher.subscribe_group("workers");
+        let second = dispatcher.subscribe_group("workers");
+        for i in 0..4 {
+            dispatcher.dispatch(msg(i)).unwrap();
+        }
+        drop(first);
+        let third = dispatcher.subscribe_group("workers");
+        dispatcher.dispatch(msg(4)).unwrap();
+        dispatcher.dispatch(msg(5)).unwrap();
+        drop(dispatcher);
+        assert_eq!(second.map(|m| *m.message).collect::<Vec<_>>(), vec![0, 1, 2, 3, 5]);
+        assert_eq!(third.map(|m| *m.message).collect::<Vec<_>>(), vec![4]);
+    }
+
+    #[test]
+    fn leaving_member_hands_over_messages_already_passed() {
+        let dispatcher = Dispatcher::new();
+        let first = dispatcher.subscribe_group("workers");
+        let second = dispatcher.subscribe_group("workers");
+        dispatcher.dispatch(msg(0)).unwrap();
+        dispatcher.dispatch(msg(1)).unwrap();
+        assert_eq!(*second.read().unwrap().message, 1);
+        // second ha già superato il messaggio 0, che però non deve andare perso
+        drop(first);
+        dispatcher.dispatch(msg(2)).unwrap();
+        drop(dispatcher);
+        assert_eq!(second.map(|m| *m.message).collect::<Vec<_>>(), vec![0, 2]);
+    }
+}
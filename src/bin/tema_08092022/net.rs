@@ -0,0 +1,497 @@
+// Espone un Dispatcher ad altri processi sulla stessa macchina tramite TCP o socket Unix.
+//
+// Ogni frame è composto da [lunghezza: u32][tipo: u8][corpo] dove la lunghezza comprende tipo e
+// corpo. I corpi dei frame sono:
+//  SUBSCRIBE   [id: u64][pattern]
+//  PUBLISH     [id: u64][lunghezza topic: u16][topic][payload]
+//  MESSAGE     [seq: u64][lunghezza topic: u16][topic][payload]
+//  SUBSCRIBED  [id: u64], conferma della SUBSCRIBE con lo stesso id
+//  ERROR       [id: u64][descrizione], errore della richiesta con lo stesso id
+// Tutti gli interi sono little endian, il payload è codificato con Codec. L'id scelto dal client
+// permette di associare ogni risposta alla richiesta che l'ha generata.
+use std::collections::{ HashMap, VecDeque };
+use std::io::{ self, Read, Write };
+use std::net::{ Shutdown, TcpListener, TcpStream, ToSocketAddrs };
+#[cfg(unix)]
+use std::os::unix::net::{ UnixListener, UnixStream };
+#[cfg(unix)]
+use std::path::Path;
+use std::sync::atomic::{ AtomicU64, Ordering };
+use std::sync::{ Arc, Condvar, Mutex };
+use std::thread::{ self, JoinHandle };
+
+use crate::storage::Codec;
+use crate::{ Dispatcher, Msg, Subscription };
+
+const SUBSCRIBE: u8 = 1;
+const PUBLISH: u8 = 2;
+const MESSAGE: u8 = 3;
+const SUBSCRIBED: u8 = 4;
+const ERROR: u8 = 5;
+
+// Limite alla dimensione di un frame, per non allocare memoria arbitraria su input malformati
+const MAX_FRAME: usize = 16 << 20;
+
+#[derive(Debug, PartialEq)]
+enum Frame {
+    Subscribe { id: u64, pattern: String },
+    Publish { id: u64, topic: String, payload: Vec<u8> },
+    Message { seq: u64, topic: String, payload: Vec<u8> },
+    Subscribed { id: u64 },
+    Error { id: u64, reason: String }
+}
+
+fn invalid(reason: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, reason)
+}
+
+fn put_topic(body: &mut Vec<u8>, topic: &str) -> io::Result<()> {
+    let len = u16::try_from(topic.len())
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "topic longer than 65535 bytes"))?;
+    body.extend_from_slice(&len.to_le_bytes());
+    body.extend_from_slice(topic.as_bytes());
+    Ok(())
+}
+
+fn take_id(body: &[u8]) -> io::Result<(u64, &[u8])> {
+    let id = u64::from_le_bytes(body.get(0..8).ok_or_else(|| invalid("short frame"))?.try_into().unwrap());
+    Ok((id, &body[8..]))
+}
+
+fn take_topic(body: &[u8]) -> io::Result<(String, &[u8])> {
+    let len = u16::from_le_bytes(body.get(0..2).ok_or_else(|| invalid("short frame"))?.try_into().unwrap()) as usize;
+    let topic = body.get(2..2 + len).ok_or_else(|| invalid("short frame"))?;
+    let topic = String::from_utf8(topic.to_vec()).map_err(|_| invalid("topic is not utf-8"))?;
+    Ok((topic, &body[2 + len..]))
+}
+
+impl Frame {
+    fn write_to(&self, stream: &mut impl Write) -> io::Result<()> {
+        let mut body = vec![];
+        let kind = match self {
+            Frame::Subscribe { id, pattern } => {
+                body.extend_from_slice(&id.to_le_bytes());
+                body.extend_from_slice(pattern.as_bytes());
+                SUBSCRIBE
+            },
+            Frame::Publish { id, topic, payload } => {
+                body.extend_from_slice(&id.to_le_bytes());
+                put_topic(&mut body, topic)?;
+                body.extend_from_slice(payload);
+                PUBLISH
+            },
+            Frame::Message { seq, topic, payload } => {
+                body.extend_from_slice(&seq.to_le_bytes());
+                put_topic(&mut body, topic)?;
+                body.extend_from_slice(payload);
+                MESSAGE
+            },
+            Frame::Subscribed { id } => {
+                body.extend_from_slice(&id.to_le_bytes());
+                SUBSCRIBED
+            },
+            Frame::Error { id, reason } => {
+                body.extend_from_slice(&id.to_le_bytes());
+                body.extend_from_slice(reason.as_bytes());
+                ERROR
+            }
+        };
+        // Il destinatario rifiuterebbe il frame
+        if body.len() >= MAX_FRAME {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "frame too large"));
+        }
+        let mut bytes = Vec::with_capacity(5 + body.len());
+        bytes.extend_from_slice(&(body.len() as u32 + 1).to_le_bytes());
+        bytes.push(kind);
+        bytes.extend_from_slice(&body);
+        stream.write_all(&bytes)?;
+        stream.flush()
+    }
+
+    // Restituisce None se la connessione è stata chiusa tra un frame e l'altro
+    fn read_from(stream: &mut impl Read) -> io::Result<Option<Frame>> {
+        let mut len = [0; 4];
+        match stream.read_exact(&mut len) {
+            Ok(()) => {},
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+            Err(e) => return Err(e)
+        }
+        let len = u32::from_le_bytes(len) as usize;
+        if len == 0 || len > MAX_FRAME {
+            return Err(invalid("bad frame length"));
+        }
+        let mut bytes = vec![0; len];
+        stream.read_exact(&mut bytes)?;
+        let body = &bytes[1..];
+        let text = |body: &[u8]| String::from_utf8(body.to_vec()).map_err(|_| invalid("text is not utf-8"));
+        let frame = match bytes[0] {
+            SUBSCRIBE => {
+                let (id, pattern) = take_id(body)?;
+                Frame::Subscribe { id, pattern: text(pattern)? }
+            },
+            PUBLISH => {
+                let (id, body) = take_id(body)?;
+                let (topic, payload) = take_topic(body)?;
+                Frame::Publish { id, topic, payload: payload.to_vec() }
+            },
+            MESSAGE => {
+                let seq = u64::from_le_bytes(body.get(0..8).ok_or_else(|| invalid("short frame"))?.try_into().unwrap());
+                let (topic, payload) = take_topic(&body[8..])?;
+                Frame::Message { seq, topic, payload: payload.to_vec() }
+            },
+            SUBSCRIBED => Frame::Subscribed { id: take_id(body)?.0 },
+            ERROR => {
+                let (id, reason) = take_id(body)?;
+                Frame::Error { id, reason: text(reason)? }
+            },
+            _ => return Err(invalid("unknown frame type"))
+        };
+        Ok(Some(frame))
+    }
+}
+
+// Connessione su cui scrivere da un thread mentre un altro legge
+pub trait Stream: Read + Write + Send + Sized + 'static {
+    fn try_clone(&self) -> io::Result<Self>;
+    // Chiude la connessione in entrambe le direzioni, sbloccando chi è in attesa di leggere
+    fn shutdown(&self) -> io::Result<()>;
+}
+
+impl Stream for TcpStream {
+    fn try_clone(&self) -> io::Result<Self> {
+        TcpStream::try_clone(self)
+    }
+
+    fn shutdown(&self) -> io::Result<()> {
+        TcpStream::shutdown(self, Shutdown::Both)
+    }
+}
+
+#[cfg(unix)]
+impl Stream for UnixStream {
+    fn try_clone(&self) -> io::Result<Self> {
+        UnixStream::try_clone(self)
+    }
+
+    fn shutdown(&self) -> io::Result<()> {
+        UnixStream::shutdown(self, Shutdown::Both)
+    }
+}
+
+pub trait Listener: Send + 'static {
+    type Stream: Stream;
+    fn accept(&self) -> io::Result<Self::Stream>;
+}
+
+impl Listener for TcpListener {
+    type Stream = TcpStream;
+    fn accept(&self) -> io::Result<TcpStream> {
+        TcpListener::accept(self).map(|(stream, _)| stream)
+    }
+}
+
+#[cfg(unix)]
+impl Listener for UnixListener {
+    type Stream = UnixStream;
+    fn accept(&self) -> io::Result<UnixStream> {
+        UnixListener::accept(self).map(|(stream, _)| stream)
+    }
+}
+
+// Accetta connessioni da listener finché questo non restituisce un errore. Ogni connessione è
+// servita da un thread, ogni SUBSCRIBE da un ulteriore thread che inoltra i messaggi della
+// Subscription corrispondente
+pub fn serve<T, L>(dispatcher: Arc<Dispatcher<T>>, listener: L) -> JoinHandle<io::Result<()>>
+where T: Codec + Clone + Send + Sync + 'static, L: Listener {
+    thread::spawn(move || {
+        loop {
+            let stream = listener.accept()?;
+            let dispatcher = Arc::clone(&dispatcher);
+            thread::spawn(move || {
+                let _ = serve_connection(dispatcher, stream);
+            });
+        }
+    })
+}
+
+fn serve_connection<T, S>(dispatcher: Arc<Dispatcher<T>>, mut stream: S) -> io::Result<()>
+where T: Codec + Clone + Send + Sync + 'static, S: Stream {
+    let writer = Arc::new(Mutex::new(stream.try_clone()?));
+    let mut subscriptions: Vec<Arc<Subscription<T>>> = vec![];
+
+    let mut handle = || loop {
+        let Some(frame) = Frame::read_from(&mut stream)? else { return Ok(()) };
+        match frame {
+            Frame::Subscribe { id, pattern } => match dispatcher.subscribe_topic(&pattern) {
+                Ok(subscription) => {
+                    let subscription = Arc::new(subscription);
+                    subscriptions.push(Arc::clone(&subscription));
+                    Frame::Subscribed { id }.write_to(&mut *writer.lock().unwrap())?;
+                    let writer = Arc::clone(&writer);
+                    thread::spawn(move || forward(&subscription, &writer));
+                },
+                Err(e) => Frame::Error { id, reason: e.to_string() }.write_to(&mut *writer.lock().unwrap())?
+            },
+            Frame::Publish { id, topic, payload } => {
+                let result = match T::decode(&payload) {
+                    Some(message) => dispatcher.dispatch_topic(&topic, Msg::new(message)).map(|_| ()),
+                    None => Err(invalid("undecodable payload"))
+                };
+                if let Err(e) = result {
+                    Frame::Error { id, reason: e.to_string() }.write_to(&mut *writer.lock().unwrap())?;
+                }
+            },
+            _ => return Err(invalid("unexpected frame from client"))
+        }
+    };
+    let result = handle();
+
+    // Con la connessione chiusa i thread di inoltro terminano, letti i messaggi pendenti
+    for subscription in subscriptions {
+        subscription.unsubscribe();
+    }
+    result
+}
+
+fn forward<T, S>(subscription: &Subscription<T>, writer: &Mutex<S>)
+where T: Codec + Clone + Sync + 'static, S: Stream {
+    for msg in subscription {
+        let mut payload = vec![];
+        msg.message.encode(&mut payload);
+        let frame = Frame::Message { seq: msg.seq, topic: msg.topic.to_string(), payload };
+        if frame.write_to(&mut *writer.lock().unwrap()).is_err() {
+            subscription.unsubscribe();
+            return;
+        }
+    }
+}
+
+// Lato client del protocollo. Un solo thread legge dalla connessione: le risposte alle SUBSCRIBE
+// vengono consegnate a chi le attende in base all'id, i messaggi di tutte le sottoscrizioni e gli
+// errori delle PUBLISH vengono restituiti da recv nell'ordine di arrivo
+pub struct Client<S: Stream> {
+    writer: Mutex<S>,
+    shared: Arc<ClientShared>,
+    next_id: AtomicU64
+}
+
+struct ClientState {
+    // Richieste in attesa di risposta, con la risposta una volta arrivata
+    replies: HashMap<u64, Option<Result<(), String>>>,
+    incoming: VecDeque<Frame>,
+    // Impostato dal thread di lettura alla chiusura della connessione, con l'eventuale errore
+    closed: Option<Option<io::ErrorKind>>
+}
+
+struct ClientShared {
+    state: Mutex<ClientState>,
+    changed: Condvar
+}
+
+impl Client<TcpStream> {
+    pub fn connect_tcp(addr: impl ToSocketAddrs) -> io::Result<Self> {
+        Client::new(TcpStream::connect(addr)?)
+    }
+}
+
+#[cfg(unix)]
+impl Client<UnixStream> {
+    pub fn connect_unix(path: impl AsRef<Path>) -> io::Result<Self> {
+        Client::new(UnixStream::connect(path)?)
+    }
+}
+
+impl <S: Stream> Client<S> {
+    pub fn new(stream: S) -> io::Result<Self> {
+        let shared = Arc::new(ClientShared {
+            state: Mutex::new(ClientState {
+                replies: HashMap::new(),
+                incoming: VecDeque::new(),
+                closed: None
+            }),
+            changed: Condvar::new()
+        });
+        let mut reader = stream.try_clone()?;
+        let reader_shared = Arc::clone(&shared);
+        thread::spawn(move || {
+            let closed = loop {
+                let frame = match Frame::read_from(&mut reader) {
+                    Ok(Some(frame)) => frame,
+                    Ok(None) => break None,
+                    Err(e) => break Some(e.kind())
+                };
+                let mut state = reader_shared.state.lock().unwrap();
+                let reply = match &frame {
+                    Frame::Subscribed { id } => Some((*id, Ok(()))),
+                    Frame::Error { id, reason } => Some((*id, Err(reason.clone()))),
+                    _ => None
+                };
+                match reply {
+                    Some((id, reply)) if state.replies.contains_key(&id) => {
+                        state.replies.insert(id, Some(reply));
+                    },
+                    _ => state.incoming.push_back(frame)
+                }
+                reader_shared.changed.notify_all();
+            };
+            reader_shared.state.lock().unwrap().closed = Some(closed);
+            reader_shared.changed.notify_all();
+        });
+        Ok(Client {
+            writer: Mutex::new(stream),
+            shared,
+            next_id: AtomicU64::new(1)
+        })
+    }
+
+    // Ritorna quando il server ha registrato la sottoscrizione, quindi i messaggi pubblicati da
+    // quel momento in poi verranno ricevuti
+    pub fn subscribe(&self, pattern: &str) -> io::Result<()> {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        // La richiesta viene registrata prima di inviarla, così la risposta non può precederla
+        self.shared.state.lock().unwrap().replies.insert(id, None);
+        let sent = Frame::Subscribe { id, pattern: pattern.to_string() }.write_to(&mut *self.writer.lock().unwrap());
+        let mut state = self.shared.state.lock().unwrap();
+        if let Err(e) = sent {
+            state.replies.remove(&id);
+            return Err(e);
+        }
+        state = self.shared.changed.wait_while(state, |state| {
+            state.replies[&id].is_none() && state.closed.is_none()
+        }).unwrap();
+        match state.replies.remove(&id).flatten() {
+            Some(Ok(())) => Ok(()),
+            Some(Err(reason)) => Err(io::Error::new(io::ErrorKind::InvalidInput, reason)),
+            None => Err(io::ErrorKind::UnexpectedEof.into())
+        }
+    }
+
+    // Un errore del server nel recapitare il messaggio viene restituito da recv
+    pub fn publish<T: Codec>(&self, topic: &str, message: &T) -> io::Result<()> {
+        let mut payload = vec![];
+        message.encode(&mut payload);
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        Frame::Publish { id, topic: topic.to_string(), payload }.write_to(&mut *self.writer.lock().unwrap())
+    }
+
+    // Blocca fino al prossimo messaggio, None se il server ha chiuso la connessione
+    pub fn recv<T: Codec + Clone + Sync + 'static>(&self) -> io::Result<Option<Msg<T>>> {
+        let mut state = self.shared.changed.wait_while(self.shared.state.lock().unwrap(), |state| {
+            state.incoming.is_empty() && state.closed.is_none()
+        }).unwrap();
+        match state.incoming.pop_front() {
+            Some(Frame::Message { seq, topic, payload }) => {
+                let message = T::decode(&payload).ok_or_else(|| invalid("undecodable payload"))?;
+                Ok(Some(Msg { seq, topic: topic.into(), ..Msg::new(message) }))
+            },
+            Some(Frame::Error { reason, .. }) => Err(io::Error::other(reason)),
+            Some(_) => Err(invalid("unexpected frame from server")),
+            None => match state.closed {
+                Some(Some(kind)) => Err(kind.into()),
+                _ => Ok(None)
+            }
+        }
+    }
+}
+
+impl <S: Stream> Drop for Client<S> {
+    fn drop(&mut self) {
+        // Il thread di lettura termina quando la connessione viene chiusa
+        let _ = self.writer.get_mut().unwrap().shutdown();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{ serve, Client, Frame };
+    use crate::{ Dispatcher, Msg };
+    use std::{ io::{ self, Cursor }, net::TcpListener, sync::Arc, thread };
+
+    #[test]
+    fn frame_roundtrip() {
+        let frames = vec![
+            Frame::Subscribe { id: 1, pattern: "orders.*".to_string() },
+            Frame::Publish { id: 2, topic: "orders.created".to_string(), payload: vec![1, 2, 3] },
+            Frame::Message { seq: 42, topic: "orders.created".to_string(), payload: vec![] },
+            Frame::Subscribed { id: 1 },
+            Frame::Error { id: 2, reason: "boom".to_string() }
+        ];
+        let mut bytes = vec![];
+        for frame in &frames {
+            frame.write_to(&mut bytes).unwrap();
+        }
+        let mut cursor = Cursor::new(bytes);
+        for frame in frames {
+            assert_eq!(Frame::read_from(&mut cursor).unwrap(), Some(frame));
+        }
+        assert_eq!(Frame::read_from(&mut cursor).unwrap(), None);
+
+        let long = Frame::Publish { id: 3, topic: "x".repeat(70_000), payload: vec![] };
+        assert_eq!(long.write_to(&mut vec![]).unwrap_err().kind(), io::ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn replies_are_routed_by_id() {
+        let dispatcher = Arc::new(Dispatcher::<u32>::new());
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        serve(Arc::clone(&dispatcher), listener);
+
+        let client = Arc::new(Client::connect_tcp(addr).unwrap());
+        // Un recv in attesa non blocca le sottoscrizioni
+        let receiver = {
+            let client = Arc::clone(&client);
+            thread::spawn(move || client.recv::<u32>().map(|msg| msg.map(|msg| *msg.message)))
+        };
+        client.subscribe("numbers").unwrap();
+        // L'errore di una PUBLISH precedente non viene scambiato per la risposta alla SUBSCRIBE
+        client.publish("numbers", &"not a number".to_string()).unwrap();
+        client.subscribe("other").unwrap();
+        assert!(receiver.join().unwrap().is_err());
+        client.publish("numbers", &7u32).unwrap();
+        assert_eq!(client.recv::<u32>().unwrap().map(|msg| *msg.message), Some(7));
+    }
+
+    #[test]
+    fn tcp_pub_sub() {
+        let dispatcher = Arc::new(Dispatcher::<String>::new());
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        serve(Arc::clone(&dispatcher), listener);
+
+        let local = dispatcher.subscribe_topic("orders.#").unwrap();
+        let remote = Client::connect_tcp(addr).unwrap();
+        remote.subscribe("orders.*").unwrap();
+        let publisher = Client::connect_tcp(addr).unwrap();
+
+        publisher.publish("orders.created", &"order 1".to_string()).unwrap();
+        let msg = remote.recv::<String>().unwrap().unwrap();
+        assert_eq!((msg.topic.as_ref(), msg.message.as_str()), ("orders.created", "order 1"));
+        assert_eq!(local.read().map(|m| m.message.to_string()), Some("order 1".to_string()));
+
+        dispatcher.dispatch_topic("orders.shipped", Msg::new("order 1".to_string())).unwrap();
+        dispatcher.dispatch_topic("payments.done", Msg::new("order 1".to_string())).unwrap();
+        dispatcher.dispatch_topic("orders.eu.created", Msg::new("order 2".to_string())).unwrap();
+        let msg = remote.recv::<String>().unwrap().unwrap();
+        assert_eq!((msg.seq, msg.topic.as_ref()), (1, "orders.shipped"));
+
+        assert!(remote.subscribe("orders..").is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn unix_socket_pub_sub() {
+        use std::os::unix::net::UnixListener;
+
+        let path = std::env::temp_dir().join(format!("tema_08092022_{}.sock", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let dispatcher = Arc::new(Dispatcher::<u32>::new());
+        serve(Arc::clone(&dispatcher), UnixListener::bind(&path).unwrap());
+
+        let client = Client::connect_unix(&path).unwrap();
+        client.subscribe("#").unwrap();
+        client.publish("numbers", &7u32).unwrap();
+        assert_eq!(client.recv::<u32>().unwrap().map(|m| *m.message), Some(7));
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,478 @@
+// Log su disco dei messaggi pubblicati dal Dispatcher, diviso in segmenti a sola aggiunta.
+//
+// Ogni segmento è un file <numero di sequenza del primo record>.log che contiene una sequenza di
+// record nel formato:
+//  [lunghezza: u32][crc32: u32][seq: u64][pubblicazione in ms: u64][lunghezza topic: u32][topic][payload]
+// dove lunghezza e crc si riferiscono a tutto ciò che segue l'intestazione. Tutti gli interi sono
+// little endian.
+use std::fs::{ self, File, OpenOptions };
+use std::io::{ self, Write };
+use std::path::PathBuf;
+use std::time::{ Duration, SystemTime, UNIX_EPOCH };
+
+const HEADER_LEN: usize = 8;
+
+// Serializzazione dei messaggi per poterli scrivere su disco
+#[allow(dead_code)]
+pub trait Codec: Sized {
+    fn encode(&self, buf: &mut Vec<u8>);
+    fn decode(bytes: &[u8]) -> Option<Self>;
+}
+
+impl Codec for String {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        buf.extend_from_slice(self.as_bytes());
+    }
+
+    fn decode(bytes: &[u8]) -> Option<Self> {
+        String::from_utf8(bytes.to_vec()).ok()
+    }
+}
+
+impl Codec for Vec<u8> {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        buf.extend_from_slice(self);
+    }
+
+    fn decode(bytes: &[u8]) -> Option<Self> {
+        Some(bytes.to_vec())
+    }
+}
+
+impl Codec for u32 {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        buf.extend_from_slice(&self.to_le_bytes());
+    }
+
+    fn decode(bytes: &[u8]) -> Option<Self> {
+        Some(u32::from_le_bytes(bytes.try_into().ok()?))
+    }
+}
+
+impl Codec for u64 {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        buf.extend_from_slice(&self.to_le_bytes());
+    }
+
+    fn decode(bytes: &[u8]) -> Option<Self> {
+        Some(u64::from_le_bytes(bytes.try_into().ok()?))
+    }
+}
+
+// CRC-32 (IEEE 802.3) calcolato bit per bit, sufficiente per riconoscere un record scritto a metà
+pub fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in bytes {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 == 1 { (crc >> 1) ^ 0xEDB8_8320 } else { crc >> 1 };
+        }
+    }
+    !crc
+}
+
+// Quando i dati scritti vengono forzati su disco
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[allow(dead_code)]
+pub enum Fsync {
+    // Dopo ogni record
+    Always,
+    // Ogni n record
+    Every(usize),
+    // Mai esplicitamente, se ne occupa il sistema operativo
+    Never
+}
+
+pub struct LogConfig {
+    pub dir: PathBuf,
+    // Dimensione oltre la quale si passa ad un nuovo segmento
+    pub segment_bytes: u64,
+    // I segmenti più vecchi vengono eliminati quando la dimensione totale supera max_bytes...
+    pub max_bytes: Option<u64>,
+    // ...o quando non sono stati modificati da più di max_age
+    pub max_age: Option<Duration>,
+    pub fsync: Fsync
+}
+
+#[allow(dead_code)]
+impl LogConfig {
+    pub fn new(dir: impl Into<PathBuf>) -> LogConfig {
+        LogConfig {
+            dir: dir.into(),
+            segment_bytes: 1 << 20,
+            max_bytes: None,
+            max_age: None,
+            fsync: Fsync::Always
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Record {
+    pub seq: u64,
+    pub published: SystemTime,
+    pub topic: String,
+    pub payload: Vec<u8>
+}
+
+#[allow(dead_code)]
+impl Record {
+    fn encode(&self) -> Vec<u8> {
+        let millis = self.published.duration_since(UNIX_EPOCH).unwrap_or_default().as_millis() as u64;
+        let mut body = Vec::with_capacity(20 + self.topic.len() + self.payload.len());
+        body.extend_from_slice(&self.seq.to_le_bytes());
+        body.extend_from_slice(&millis.to_le_bytes());
+        body.extend_from_slice(&(self.topic.len() as u32).to_le_bytes());
+        body.extend_from_slice(self.topic.as_bytes());
+        body.extend_from_slice(&self.payload);
+
+        let mut bytes = Vec::with_capacity(HEADER_LEN + body.len());
+        bytes.extend_from_slice(&(body.len() as u32).to_le_bytes());
+        bytes.extend_from_slice(&crc32(&body).to_le_bytes());
+        bytes.extend_from_slice(&body);
+        bytes
+    }
+
+    // Restituisce il record e la sua dimensione su disco, None se il record è incompleto o corrotto
+    fn decode(bytes: &[u8]) -> Option<(Record, usize)> {
+        let len = u32::from_le_bytes(bytes.get(0..4)?.try_into().ok()?) as usize;
+        let crc = u32::from_le_bytes(bytes.get(4..8)?.try_into().ok()?);
+        let body = bytes.get(HEADER_LEN..HEADER_LEN + len)?;
+        if crc32(body) != crc || len < 20 {
+            return None;
+        }
+        let seq = u64::from_le_bytes(body[0..8].try_into().ok()?);
+        let millis = u64::from_le_bytes(body[8..16].try_into().ok()?);
+        let topic_len = u32::from_le_bytes(body[16..20].try_into().ok()?) as usize;
+        let topic = String::from_utf8(body.get(20..20 + topic_len)?.to_vec()).ok()?;
+        let record = Record {
+            seq,
+            published: UNIX_EPOCH + Duration::from_millis(millis),
+            topic,
+            payload: body[20 + topic_len..].to_vec()
+        };
+        Some((record, HEADER_LEN + len))
+    }
+}
+
+struct Segment {
+    path: PathBuf,
+    size: u64
+}
+
+pub struct SegmentLog {
+    config: LogConfig,
+    // In ordine di numero di sequenza, l'ultimo è quello attivo
+    segments: Vec<Segment>,
+    active: File,
+    unsynced: usize,
+    next_seq: u64
+}
+
+#[allow(dead_code)]
+impl SegmentLog {
+    // Apre il log esistente (o ne crea uno nuovo) e restituisce i record validi già presenti. Un
+    // record scritto solo in parte alla fine dell'ultimo segmento, ad esempio per un crash, viene
+    // eliminato troncando il file; un record corrotto in un punto precedente è un errore, perché
+    // proseguire lascerebbe un buco nei numeri di sequenza
+    pub fn open(config: LogConfig) -> io::Result<(SegmentLog, Vec<Record>)> {
+        fs::create_dir_all(&config.dir)?;
+        let mut bases = vec![];
+        for entry in fs::read_dir(&config.dir)? {
+            let path = entry?.path();
+            if path.extension().is_some_and(|extension| extension == "log") {
+                if let Some(base) = path.file_stem().and_then(|stem| stem.to_str()?.parse::<u64>().ok()) {
+                    bases.push((base, path));
+                }
+            }
+        }
+        bases.sort();
+
+        let mut records: Vec<Record> = vec![];
+        let mut segments = vec![];
+        // Numero di record letti da ogni segmento
+        let mut counts = vec![];
+        // Il nome del segmento è il numero di sequenza del suo primo record: se la conservazione ha
+        // eliminato i segmenti precedenti e quello attivo è vuoto, è l'unica traccia della
+        // posizione raggiunta
+        let mut next_seq = 0;
+        let last_segment = bases.len().saturating_sub(1);
+        for (index, (base, path)) in bases.into_iter().enumerate() {
+            next_seq = next_seq.max(base);
+            let bytes = fs::read(&path)?;
+            let mut valid = 0;
+            let first = records.len();
+            while let Some((record, len)) = Record::decode(&bytes[valid..]) {
+                let expected = records.last().map_or(base, |last| last.seq + 1);
+                if record.seq != expected {
+                    return Err(io::Error::new(io::ErrorKind::InvalidData, format!(
+                        "{}: atteso il record {}, trovato {}", path.display(), expected, record.seq
+                    )));
+                }
+                records.push(record);
+                valid += len;
+            }
+            if valid < bytes.len() {
+                if index < last_segment {
+                    return Err(io::Error::new(io::ErrorKind::InvalidData, format!(
+                        "{}: record corrotto alla posizione {}", path.display(), valid
+                    )));
+                }
+                OpenOptions::new().write(true).open(&path)?.set_len(valid as u64)?;
+            }
+            segments.push(Segment { path, size: valid as u64 });
+            counts.push(records.len() - first);
+        }
+
+        if let Some(last) = records.last() {
+            next_seq = next_seq.max(last.seq + 1);
+        }
+        if segments.is_empty() {
+            segments.push(Segment { path: SegmentLog::segment_path(&config, next_seq), size: 0 });
+        }
+        let active = OpenOptions::new().create(true).append(true).open(&segments[segments.len() - 1].path)?;
+        let mut log = SegmentLog {
+            config,
+            segments,
+            active,
+            unsynced: 0,
+            next_seq
+        };
+        // I segmenti scaduti mentre il log era chiuso vengono eliminati insieme ai loro record
+        let before = log.segments.len();
+        log.cleanup()?;
+        let removed: usize = counts[..before - log.segments.len()].iter().sum();
+        records.drain(..removed);
+        Ok((log, records))
+    }
+
+    fn segment_path(config: &LogConfig, base: u64) -> PathBuf {
+        config.dir.join(format!("{:020}.log", base))
+    }
+
+    pub fn next_seq(&self) -> u64 {
+        self.next_seq
+    }
+
+    pub fn append(&mut self, record: &Record) -> io::Result<()> {
+        let active_size = self.segments[self.segments.len() - 1].size;
+        if active_size > 0 && active_size >= self.config.segment_bytes {
+            self.roll(record.seq)?;
+        }
+
+        let bytes = record.encode();
+        let last = self.segments.len() - 1;
+        if let Err(error) = self.active.write_all(&bytes) {
+            // Un record scritto in parte resterebbe in mezzo al segmento, prima di quelli successivi:
+            // il file torna all'ultima posizione valida
+            self.active.set_len(self.segments[last].size)?;
+            return Err(error);
+        }
+        self.segments[last].size += bytes.len() as u64;
+        self.next_seq = record.seq + 1;
+        self.unsynced += 1;
+        match self.config.fsync {
+            Fsync::Always => self.sync(),
+            Fsync::Every(n) if self.unsynced >= n => self.sync(),
+            _ => Ok(())
+        }
+    }
+
+    pub fn sync(&mut self) -> io::Result<()> {
+        self.unsynced = 0;
+        self.active.sync_data()
+    }
+
+    fn roll(&mut self, base: u64) -> io::Result<()> {
+        if self.config.fsync != Fsync::Never {
+            self.sync()?;
+        }
+        let path = SegmentLog::segment_path(&self.config, base);
+        self.active = OpenOptions::new().create(true).append(true).open(&path)?;
+        self.segments.push(Segment { path, size: 0 });
+        self.cleanup()
+    }
+
+    // Elimina i segmenti più vecchi secondo max_bytes e max_age, quello attivo non viene mai
+    // eliminato
+    fn cleanup(&mut self) -> io::Result<()> {
+        while self.segments.len() > 1 {
+            let total: u64 = self.segments.iter().map(|segment| segment.size).sum();
+            let oldest = &self.segments[0];
+            let too_big = self.config.max_bytes.is_some_and(|max| total > max);
+            let too_old = match self.config.max_age {
+                Some(max_age) => fs::metadata(&oldest.path)?.modified()?.elapsed().unwrap_or_default() > max_age,
+                None => false
+            };
+            if !too_big && !too_old {
+                break;
+            }
+            fs::remove_file(&oldest.path)?;
+            self.segments.remove(0);
+        }
+        Ok(())
+    }
+}
+
+impl Drop for SegmentLog {
+    fn drop(&mut self) {
+        if self.config.fsync != Fsync::Never && self.unsynced > 0 {
+            let _ = self.sync();
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{ crc32, Fsync, LogConfig, Record, SegmentLog };
+    use std::{ fs, io::Write, path::PathBuf, time::SystemTime };
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("tema_08092022_{}_{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    fn record(seq: u64) -> Record {
+        Record {
+            seq,
+            published: SystemTime::now(),
+            topic: "orders.created".to_string(),
+            payload: vec![seq as u8; 10]
+        }
+    }
+
+    fn segment_files(dir: &PathBuf) -> Vec<String> {
+        let mut files: Vec<String> = fs::read_dir(dir).unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .collect();
+        files.sort();
+        files
+    }
+
+    #[test]
+    fn crc32_check_value() {
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+    }
+
+    #[test]
+    fn reopen_recovers_records() {
+        let dir = temp_dir("reopen");
+        {
+            let (mut log, records) = SegmentLog::open(LogConfig::new(&dir)).unwrap();
+            assert!(records.is_empty());
+            for seq in 0..5 {
+                log.append(&record(seq)).unwrap();
+            }
+        }
+        let (log, records) = SegmentLog::open(LogConfig::new(&dir)).unwrap();
+        assert_eq!(log.next_seq(), 5);
+        assert_eq!(records.iter().map(|record| record.seq).collect::<Vec<_>>(), vec![0, 1, 2, 3, 4]);
+        assert_eq!(records[3].payload, vec![3; 10]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn truncated_tail_is_repaired() {
+        let dir = temp_dir("truncated");
+        {
+            let (mut log, _) = SegmentLog::open(LogConfig::new(&dir)).unwrap();
+            log.append(&record(0)).unwrap();
+            log.append(&record(1)).unwrap();
+        }
+        let path = dir.join(&segment_files(&dir)[0]);
+        let len = fs::metadata(&path).unwrap().len();
+        // Simula un crash durante la scrittura del terzo record
+        let partial = record(2).encode();
+        fs::OpenOptions::new().append(true).open(&path).unwrap().write_all(&partial[..partial.len() / 2]).unwrap();
+
+        let (mut log, records) = SegmentLog::open(LogConfig::new(&dir)).unwrap();
+        assert_eq!(records.len(), 2);
+        assert_eq!(fs::metadata(&path).unwrap().len(), len);
+        log.append(&record(2)).unwrap();
+        drop(log);
+        assert_eq!(SegmentLog::open(LogConfig::new(&dir)).unwrap().1.len(), 3);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn next_seq_survives_empty_active_segment() {
+        let dir = temp_dir("empty_active");
+        fs::create_dir_all(&dir).unwrap();
+        // I segmenti precedenti sono stati eliminati e quello attivo non contiene record
+        fs::File::create(dir.join(format!("{:020}.log", 7))).unwrap();
+        let (mut log, records) = SegmentLog::open(LogConfig::new(&dir)).unwrap();
+        assert!(records.is_empty());
+        assert_eq!(log.next_seq(), 7);
+        log.append(&record(7)).unwrap();
+        drop(log);
+        assert_eq!(SegmentLog::open(LogConfig::new(&dir)).unwrap().0.next_seq(), 8);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn corruption_before_the_tail_is_an_error() {
+        let dir = temp_dir("corrupt");
+        let config = || {
+            let mut config = LogConfig::new(&dir);
+            config.segment_bytes = 100;
+            config
+        };
+        let (mut log, _) = SegmentLog::open(config()).unwrap();
+        for seq in 0..6 {
+            log.append(&record(seq)).unwrap();
+        }
+        drop(log);
+        let first = dir.join(&segment_files(&dir)[0]);
+        let mut bytes = fs::read(&first).unwrap();
+        let last = bytes.len() - 1;
+        bytes[last] ^= 0xFF;
+        fs::write(&first, bytes).unwrap();
+        let error = SegmentLog::open(config()).err().unwrap();
+        assert_eq!(error.kind(), std::io::ErrorKind::InvalidData);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn retention_applies_on_open() {
+        let dir = temp_dir("retention_on_open");
+        let (mut log, _) = SegmentLog::open(LogConfig { segment_bytes: 100, ..LogConfig::new(&dir) }).unwrap();
+        for seq in 0..6 {
+            log.append(&record(seq)).unwrap();
+        }
+        drop(log);
+        assert_eq!(segment_files(&dir).len(), 3);
+
+        let config = LogConfig { segment_bytes: 100, max_bytes: Some(150), ..LogConfig::new(&dir) };
+        let (log, records) = SegmentLog::open(config).unwrap();
+        assert_eq!(segment_files(&dir), vec![format!("{:020}.log", 4)]);
+        assert_eq!(records.iter().map(|record| record.seq).collect::<Vec<_>>(), vec![4, 5]);
+        assert_eq!(log.next_seq(), 6);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn rollover_and_size_cleanup() {
+        let dir = temp_dir("rollover");
+        let config = || {
+            let mut config = LogConfig::new(&dir);
+            config.segment_bytes = 100;
+            config.max_bytes = Some(250);
+            config.fsync = Fsync::Every(4);
+            config
+        };
+        let (mut log, _) = SegmentLog::open(config()).unwrap();
+        for seq in 0..20 {
+            log.append(&record(seq)).unwrap();
+        }
+        drop(log);
+        let files = segment_files(&dir);
+        assert!(files.len() > 1 && files.len() <= 4);
+        assert_eq!(files.last().unwrap(), &format!("{:020}.log", 18));
+
+        let (log, records) = SegmentLog::open(config()).unwrap();
+        assert_eq!(log.next_seq(), 20);
+        assert!(records.first().unwrap().seq > 0);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
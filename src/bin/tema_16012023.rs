@@ -13,12 +13,18 @@
 // invocato wait() otterrà 1 come valore di ritorno, il secondo thread 2, e così via. All'inizio di un nuovo ciclo, il conteggio ripartirà da 1.
 
 // Si implementi la struttura dati RankingBarrier a scelta nei linguaggi Rust o C++ '11 o successivi.
+use std::future::poll_fn;
 use std::thread::spawn;
 use std::sync::{ Arc, Condvar, Mutex };
+use std::task::Poll;
+use soluzione_temi_malnati::task::Wakers;
 
 struct BarrierState {
     arrival: usize,
-    waiting: usize
+    // Incrementata ad ogni apertura, chi attende si sblocca quando cambia
+    generation: usize,
+    // Task in attesa in wait_async
+    wakers: Wakers
 }
 
 struct RankingBarrier {
@@ -31,10 +37,11 @@ impl RankingBarrier {
     
     pub fn new(n: usize) -> RankingBarrier {
         let arrival = 0;
-        let waiting = 0;
+        let generation = 0;
         let state = BarrierState {
             arrival,
-            waiting
+            generation,
+            wakers: Wakers::new()
         };
         let condvar = Condvar::new();
         RankingBarrier {
@@ -46,14 +53,39 @@ impl RankingBarrier {
 
     pub fn wait(&self, _i: usize) -> usize { 
         let mut lock = self.state.lock().unwrap();
-        let local_arrival = lock.arrival;
-        lock.arrival += 1;
-        lock.waiting += 1;
-        let _guard = self.condvar.wait_while(lock, |state| state.waiting  < self.n - 1).unwrap();
-        self.condvar.notify_all();
+        let (local_arrival, generation) = self.arrive(&mut lock);
+        let _guard = self.condvar.wait_while(lock, |state| state.generation == generation).unwrap();
         local_arrival
     }
 
+    #[allow(dead_code)]
+    pub async fn wait_async(&self) -> usize {
+        let mut arrival = None;
+        poll_fn(|cx| {
+            let mut lock = self.state.lock().unwrap();
+            let (local_arrival, generation) = *arrival.get_or_insert_with(|| self.arrive(&mut lock));
+            if lock.generation != generation {
+                return Poll::Ready(local_arrival);
+            }
+            lock.wakers.register(cx.waker());
+            Poll::Pending
+        }).await
+    }
+
+    // L'ultimo degli n arrivi apre la barriera e fa ripartire il conteggio da 1
+    fn arrive(&self, state: &mut BarrierState) -> (usize, usize) {
+        state.arrival += 1;
+        let local_arrival = state.arrival;
+        let generation = state.generation;
+        if state.arrival == self.n {
+            state.arrival = 0;
+            state.generation += 1;
+            self.condvar.notify_all();
+            state.wakers.wake_all();
+        }
+        (local_arrival, generation)
+    }
+
 }
 
 pub fn main() {
@@ -75,4 +107,34 @@ pub fn main() {
        let _ = handle.join();
    }
 
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod test {
+    use crate::RankingBarrier;
+    use soluzione_temi_malnati::task::block_on;
+    use std::sync::Arc;
+    use std::thread;
+
+    #[test]
+    fn async_wait_ranks_each_cycle() {
+        let n = 4;
+        let barrier = Arc::new(RankingBarrier::new(n));
+        let handles: Vec<_> = (0..n).map(|_| {
+            let barrier = Arc::clone(&barrier);
+            thread::spawn(move || block_on(async {
+                let mut arrivals = vec![];
+                for _ in 0..3 {
+                    arrivals.push(barrier.wait_async().await);
+                }
+                arrivals
+            }))
+        }).collect();
+        let arrivals: Vec<Vec<usize>> = handles.into_iter().map(|handle| handle.join().unwrap()).collect();
+        for cycle in 0..3 {
+            let mut ranks: Vec<usize> = arrivals.iter().map(|arrivals| arrivals[cycle]).collect();
+            ranks.sort();
+            assert_eq!(ranks, (1..=n).collect::<Vec<_>>());
+        }
+    }
+}
@@ -0,0 +1,701 @@
+// Attori costruiti sul Looper: ogni attore ha una propria coda di messaggi elaborata in ordine da
+// un solo thread, quindi il suo stato non richiede sincronizzazione.
+use std::any::Any;
+use std::collections::HashMap;
+use std::fmt;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use crate::executor::{Executor, ExecutorShared};
+use crate::{Looper, Runner, Shared, SupervisionPolicy};
+use soluzione_temi_malnati::future::{Future, FutureError, Promise};
+use soluzione_temi_malnati::panic_message;
+
+#[allow(dead_code)]
+type ActorId = u64;
+
+#[allow(dead_code)]
+static NEXT_ACTOR_ID: AtomicU64 = AtomicU64::new(0);
+
+// Avvisa il genitore della terminazione di un figlio
+#[allow(dead_code)]
+type NotifyParent = Box<dyn FnOnce(ActorId, Option<String>) + Send>;
+
+// Decide cosa fare quando un attore va in panic, a partire dal suo id e dal messaggio del panic
+#[allow(dead_code)]
+type Supervise = fn(ActorId, &str) -> SupervisionPolicy;
+
+#[allow(dead_code)]
+trait Actor: Sized + Send + 'static {
+    type Msg: Send + 'static;
+
+    fn handle(&mut self, msg: Self::Msg, ctx: &mut Context<Self>);
+
+    // Chiamata nel thread dell'attore una volta prima del primo messaggio, e di nuovo dopo un
+    // Restart
+    fn started(&mut self, _ctx: &mut Context<Self>) {}
+
+    // Chiamata quando l'attore sta per terminare, prima che terminino i suoi figli
+    fn stopping(&mut self, _ctx: &mut Context<Self>) {}
+
+    fn stopped(&mut self) {}
+
+    // Un figlio è terminato, failure è il messaggio del panic se è stato fermato dalla
+    // SupervisionPolicy::Stop
+    fn child_stopped(&mut self, _child: ActorId, _failure: Option<&str>, _ctx: &mut Context<Self>) {}
+
+    // Decisa dal genitore: cosa fare quando handle, started o child_stopped di un suo figlio va
+    // in panic. Con Restart il figlio viene ricreato da capo, gli attori radice vengono sempre
+    // riavviati
+    fn supervise(_child: ActorId, _failure: &str) -> SupervisionPolicy {
+        SupervisionPolicy::Restart
+    }
+}
+
+#[allow(dead_code)]
+enum Envelope<A: Actor> {
+    Started,
+    Msg(A::Msg),
+    Stop,
+    ChildStopped(ActorId, Option<String>)
+}
+
+// Canale monouso con cui un attore risponde ad una richiesta inviata con Addr::ask
+#[allow(dead_code)]
+struct Reply<T>(Promise<T>);
+
+#[allow(dead_code)]
+impl<T> Reply<T> {
+    fn send(self, value: T) {
+        // Chi ha fatto la richiesta potrebbe aver smesso di attendere
+        self.0.set(value);
+    }
+
+    // La richiesta è stata cancellata da chi l'ha inviata
+    fn is_cancelled(&self) -> bool {
+        self.0.is_cancelled()
+    }
+}
+
+#[derive(Debug, PartialEq)]
+#[allow(dead_code)]
+enum AskError {
+    // L'attore è terminato prima di ricevere la richiesta
+    Stopped,
+    // L'attore ha scartato la richiesta senza rispondere
+    NoReply,
+    Timeout
+}
+
+impl fmt::Display for AskError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AskError::Stopped => write!(f, "the actor has stopped"),
+            AskError::NoReply => write!(f, "the actor dropped the request without replying"),
+            AskError::Timeout => write!(f, "no reply before the timeout")
+        }
+    }
+}
+
+impl std::error::Error for AskError {}
+
+// Indirizzo tipizzato di un attore, può essere clonato e inviato ad altri thread
+#[allow(dead_code)]
+struct Addr<A: Actor> {
+    id: ActorId,
+    shared: Arc<Shared<Envelope<A>>>
+}
+
+impl<A: Actor> Clone for Addr<A> {
+    fn clone(&self) -> Self {
+        Addr {
+            id: self.id,
+            shared: Arc::clone(&self.shared)
+        }
+    }
+}
+
+#[allow(dead_code)]
+impl<A: Actor> Addr<A> {
+    // Started è il primo messaggio in coda perché nessun altro conosce ancora l'indirizzo
+    fn new() -> Self {
+        let addr = Addr {
+            id: NEXT_ACTOR_ID.fetch_add(1, Ordering::Relaxed),
+            shared: Arc::new(Shared::new(None))
+        };
+        let _ = addr.shared.enqueue(Envelope::Started, |queue, msg| queue.ready.push_back(msg));
+        addr
+    }
+
+    fn id(&self) -> ActorId {
+        self.id
+    }
+
+    pub fn tell(&self, msg: A::Msg) -> Result<(), Box<dyn std::error::Error>> {
+        self.shared.enqueue(Envelope::Msg(msg), |queue, msg| queue.ready.push_back(msg))
+    }
+
+    // request costruisce il messaggio a partire dal canale su cui l'attore risponderà
+    pub fn ask<T: Send + 'static>(&self, request: impl FnOnce(Reply<T>) -> A::Msg) -> Result<T, AskError> {
+        self.ask_future(request)?.get().map_err(|_| AskError::NoReply)
+    }
+
+    pub fn ask_timeout<T: Send + 'static>(&self, request: impl FnOnce(Reply<T>) -> A::Msg, timeout: Duration) -> Result<T, AskError> {
+        let response = self.ask_future(request)?;
+        response.get_timeout(timeout).map_err(|e| match e {
+            FutureError::Timeout => {
+                response.cancel();
+                AskError::Timeout
+            },
+            _ => AskError::NoReply
+        })
+    }
+
+    // Invia la richiesta senza attendere la risposta
+    pub fn ask_future<T: Send + 'static>(&self, request: impl FnOnce(Reply<T>) -> A::Msg) -> Result<Future<T>, AskError> {
+        let (reply, response) = Promise::new();
+        self.tell(request(Reply(reply))).map_err(|_| AskError::Stopped)?;
+        Ok(response)
+    }
+
+    // L'attore termina dopo aver elaborato i messaggi già in coda
+    pub fn stop(&self) {
+        let _ = self.shared.enqueue(Envelope::Stop, |queue, msg| queue.ready.push_back(msg));
+    }
+}
+
+// Attore in esecuzione, posseduto dal System o dal genitore
+#[allow(dead_code)]
+trait Running: Send {
+    fn stop(&self);
+    fn is_finished(&self) -> bool;
+}
+
+#[allow(dead_code)]
+struct ActorHandle<A: Actor> {
+    addr: Addr<A>,
+    looper: Looper<Envelope<A>>
+}
+
+impl<A: Actor> Drop for ActorHandle<A> {
+    // La coda non viene chiusa: l'attore deve ancora ricevere ChildStopped dai figli e la chiude
+    // da sé quando sono terminati tutti
+    fn drop(&mut self) {
+        self.addr.stop();
+        self.looper.wait_stopped();
+    }
+}
+
+impl<A: Actor> Running for ActorHandle<A> {
+    fn stop(&self) {
+        self.addr.stop();
+    }
+
+    fn is_finished(&self) -> bool {
+        self.looper.runner.as_ref().is_none_or(Runner::is_finished)
+    }
+}
+
+// Nomi con cui gli attori possono essere trovati, condivisi da tutti gli attori del System
+#[derive(Default)]
+#[allow(dead_code)]
+struct Registry {
+    names: Mutex<HashMap<String, (ActorId, AnyAddr)>>
+}
+
+// Un Addr<A> di cui si conosce il tipo solo in lookup
+#[allow(dead_code)]
+type AnyAddr = Box<dyn Any + Send>;
+
+#[allow(dead_code)]
+impl Registry {
+    fn register<A: Actor>(&self, name: &str, addr: &Addr<A>) -> bool {
+        let mut names = self.names.lock().unwrap();
+        if names.contains_key(name) {
+            return false;
+        }
+        names.insert(name.to_string(), (addr.id, Box::new(addr.clone())));
+        true
+    }
+
+    // None anche se l'attore registrato con quel nome non è di tipo A
+    fn lookup<A: Actor>(&self, name: &str) -> Option<Addr<A>> {
+        self.names.lock().unwrap().get(name).and_then(|(_, addr)| addr.downcast_ref::<Addr<A>>()).cloned()
+    }
+
+    fn unregister_all(&self, id: ActorId) {
+        self.names.lock().unwrap().retain(|_, (registered, _)| *registered != id);
+    }
+}
+
+#[allow(dead_code)]
+struct Context<A: Actor> {
+    addr: Addr<A>,
+    registry: Arc<Registry>,
+    // I figli vengono eseguiti dallo stesso Executor del genitore
+    executor: Option<Arc<ExecutorShared>>,
+    parent: Option<NotifyParent>,
+    children: HashMap<ActorId, Box<dyn Running>>,
+    stopping: bool,
+    // L'attore ha chiamato stopping e attende la terminazione dei figli
+    draining: bool,
+    failure: Option<String>
+}
+
+#[allow(dead_code)]
+impl<A: Actor> Context<A> {
+    fn addr(&self) -> Addr<A> {
+        self.addr.clone()
+    }
+
+    // L'attore termina appena finita l'elaborazione del messaggio corrente, i messaggi in coda
+    // vengono scartati
+    fn stop(&mut self) {
+        self.stopping = true;
+    }
+
+    // Il figlio termina prima del genitore e il genitore viene avvisato con child_stopped. I
+    // panic del figlio sono gestiti secondo A::supervise
+    fn spawn<C: Actor>(&mut self, factory: impl FnMut() -> C + Send + 'static) -> Addr<C> {
+        let parent = self.addr.clone();
+        let notify: NotifyParent = Box::new(move |id, failure| {
+            let _ = parent.shared.enqueue(Envelope::ChildStopped(id, failure), |queue, msg| queue.ready.push_back(msg));
+        });
+        let handle = spawn_actor(Addr::new(), Arc::clone(&self.registry), self.executor.as_ref(), factory, A::supervise, Some(notify));
+        let addr = handle.addr.clone();
+        self.children.insert(addr.id, Box::new(handle));
+        addr
+    }
+
+    fn register(&self, name: &str) -> bool {
+        self.registry.register(name, &self.addr)
+    }
+
+    fn lookup<B: Actor>(&self, name: &str) -> Option<Addr<B>> {
+        self.registry.lookup(name)
+    }
+}
+
+#[allow(dead_code)]
+fn spawn_actor<A: Actor>(
+    addr: Addr<A>,
+    registry: Arc<Registry>,
+    executor: Option<&Arc<ExecutorShared>>,
+    mut factory: impl FnMut() -> A + Send + 'static,
+    supervise: Supervise,
+    parent: Option<NotifyParent>
+) -> ActorHandle<A> {
+    let mut state = Some((factory(), Context {
+        addr: addr.clone(),
+        registry,
+        executor: executor.cloned(),
+        parent,
+        children: HashMap::new(),
+        stopping: false,
+        draining: false,
+        failure: None
+    }));
+    let init = move || state.take().expect("lo stato dell'attore viene creato una sola volta");
+
+    let process = move |(actor, ctx): &mut (A, Context<A>), envelope| {
+        if ctx.draining {
+            // Durante la terminazione conta solo l'avviso dei figli, gli altri messaggi vengono
+            // scartati
+            if let Envelope::ChildStopped(id, _) = envelope {
+                ctx.children.remove(&id);
+            }
+        } else {
+            let failure = match envelope {
+                Envelope::Started => catch_failure(|| actor.started(ctx)),
+                Envelope::Msg(msg) => catch_failure(|| actor.handle(msg, ctx)),
+                Envelope::Stop => {
+                    ctx.stopping = true;
+                    None
+                },
+                Envelope::ChildStopped(id, failure) => {
+                    ctx.children.remove(&id);
+                    catch_failure(|| actor.child_stopped(id, failure.as_deref(), ctx))
+                }
+            };
+            if let Some(failure) = failure {
+                match supervise(ctx.addr.id, &failure) {
+                    SupervisionPolicy::Skip => {},
+                    SupervisionPolicy::Restart => {
+                        *actor = factory();
+                        // Ricreare di nuovo un attore che va in panic già in started ripeterebbe
+                        // lo stesso panic, quindi viene fermato
+                        if let Some(failure) = catch_failure(|| actor.started(ctx)) {
+                            ctx.failure = Some(failure);
+                            ctx.stopping = true;
+                        }
+                    },
+                    SupervisionPolicy::Stop => {
+                        ctx.failure = Some(failure);
+                        ctx.stopping = true;
+                    }
+                }
+            }
+            if ctx.stopping {
+                ctx.draining = true;
+                // L'attore sta già terminando: un panic in stopping viene solo segnalato al
+                // genitore, i figli vanno fermati comunque
+                if let Some(failure) = catch_failure(|| actor.stopping(ctx)) {
+                    ctx.failure.get_or_insert(failure);
+                }
+                for child in ctx.children.values() {
+                    child.stop();
+                }
+            }
+        }
+        // Attendere i figli bloccherebbe il thread, che con un Executor può servire proprio a loro
+        if ctx.draining && ctx.children.is_empty() {
+            ctx.addr.shared.abort();
+        }
+    };
+
+    // I figli sono già terminati
+    let cleanup = |(mut actor, mut ctx): (A, Context<A>)| {
+        // Il genitore va avvisato anche se stopped va in panic
+        if let Some(failure) = catch_failure(|| actor.stopped()) {
+            ctx.failure.get_or_insert(failure);
+        }
+        ctx.registry.unregister_all(ctx.addr.id);
+        if let Some(parent) = ctx.parent.take() {
+            parent(ctx.addr.id, ctx.failure.take());
+        }
+    };
+
+    let looper = Looper::spawn(Arc::clone(&addr.shared), executor, init, process, cleanup, SupervisionPolicy::Skip, |_| {});
+    ActorHandle { addr, looper }
+}
+
+// Esegue un hook dell'attore, restituendo il messaggio dell'eventuale panic
+fn catch_failure(hook: impl FnOnce()) -> Option<String> {
+    panic::catch_unwind(AssertUnwindSafe(hook)).err()
+        .map(|payload| panic_message(&*payload).unwrap_or("panic").to_string())
+}
+
+// Possiede gli attori radice: il drop li ferma e attende la terminazione di tutto l'albero
+#[derive(Default)]
+#[allow(dead_code)]
+struct System {
+    actors: Mutex<Vec<Box<dyn Running>>>,
+    registry: Arc<Registry>,
+    executor: Option<Arc<ExecutorShared>>
+}
+
+#[allow(dead_code)]
+impl System {
+    // Ogni attore ha un proprio thread
+    fn new() -> Self {
+        System::default()
+    }
+
+    // Gli attori vengono eseguiti dai worker di executor. Un ask tra attori dello stesso
+    // Executor occupa un worker finché non arriva la risposta
+    fn with_executor(executor: &Executor) -> Self {
+        System {
+            actors: Mutex::default(),
+            registry: Arc::default(),
+            executor: Some(Arc::clone(&executor.shared))
+        }
+    }
+
+    // factory viene richiamata per ricreare l'attore dopo un panic con SupervisionPolicy::Restart
+    fn spawn<A: Actor>(&self, factory: impl FnMut() -> A + Send + 'static) -> Addr<A> {
+        self.start(Addr::new(), factory)
+    }
+
+    // Restituisce None se il nome è già in uso. Il nome viene riservato prima di creare l'attore,
+    // quindi in quel caso factory non viene mai chiamata
+    fn spawn_named<A: Actor>(&self, name: &str, factory: impl FnMut() -> A + Send + 'static) -> Option<Addr<A>> {
+        let addr = Addr::new();
+        if !self.registry.register(name, &addr) {
+            return None;
+        }
+        Some(self.start(addr, factory))
+    }
+
+    fn start<A: Actor>(&self, addr: Addr<A>, factory: impl FnMut() -> A + Send + 'static) -> Addr<A> {
+        let handle = spawn_actor(addr, Arc::clone(&self.registry), self.executor.as_ref(), factory, |_, _| SupervisionPolicy::Restart, None);
+        let addr = handle.addr.clone();
+        let mut actors = self.actors.lock().unwrap();
+        actors.retain(|actor| !actor.is_finished());
+        actors.push(Box::new(handle));
+        addr
+    }
+
+    fn register<A: Actor>(&self, name: &str, addr: &Addr<A>) -> bool {
+        self.registry.register(name, addr)
+    }
+
+    fn lookup<A: Actor>(&self, name: &str) -> Option<Addr<A>> {
+        self.registry.lookup(name)
+    }
+
+    fn shutdown(&self) {
+        let actors = std::mem::take(&mut *self.actors.lock().unwrap());
+        for actor in &actors {
+            actor.stop();
+        }
+    }
+}
+
+impl Drop for System {
+    fn drop(&mut self) {
+        self.shutdown();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Actor, ActorId, Addr, AskError, Context, Reply, System};
+    use crate::executor::Executor;
+    use soluzione_temi_malnati::future::join_all;
+    use crate::{Looper, SupervisionPolicy};
+    use std::sync::{mpsc, Arc, Mutex};
+    use std::thread::sleep;
+    use std::time::{Duration, Instant};
+
+    enum CounterMsg {
+        Add(u32),
+        Get(Reply<u32>),
+        Fail
+    }
+
+    #[derive(Default)]
+    struct Counter {
+        count: u32
+    }
+
+    impl Actor for Counter {
+        type Msg = CounterMsg;
+
+        fn handle(&mut self, msg: CounterMsg, _ctx: &mut Context<Self>) {
+            match msg {
+                CounterMsg::Add(n) => self.count += n,
+                CounterMsg::Get(reply) => reply.send(self.count),
+                CounterMsg::Fail => panic!("counter failed")
+            }
+        }
+    }
+
+    #[test]
+    fn tell_and_ask() {
+        let system = System::new();
+        let counter = system.spawn(Counter::default);
+        counter.tell(CounterMsg::Add(2)).unwrap();
+        counter.tell(CounterMsg::Add(3)).unwrap();
+        assert_eq!(counter.ask(CounterMsg::Get), Ok(5));
+        // Con Restart l'attore riparte da capo e continua a rispondere
+        counter.tell(CounterMsg::Fail).unwrap();
+        assert_eq!(counter.ask_timeout(CounterMsg::Get, Duration::from_secs(1)), Ok(0));
+        // Il drop del System attende la terminazione dell'attore
+        drop(system);
+        assert_eq!(counter.ask(CounterMsg::Get), Err(AskError::Stopped));
+    }
+
+    type Events = Arc<Mutex<Vec<String>>>;
+
+    struct Worker {
+        events: Events
+    }
+
+    impl Actor for Worker {
+        type Msg = CounterMsg;
+
+        fn handle(&mut self, msg: CounterMsg, _ctx: &mut Context<Self>) {
+            if let CounterMsg::Fail = msg {
+                panic!("worker failed");
+            }
+        }
+
+        fn stopped(&mut self) {
+            self.events.lock().unwrap().push("worker stopped".to_string());
+        }
+    }
+
+    enum SupervisorMsg {
+        Workers(Reply<Vec<ActorId>>),
+        FailWorker
+    }
+
+    struct Supervisor {
+        events: Events,
+        workers: Vec<Addr<Worker>>,
+        failed: mpsc::Sender<ActorId>
+    }
+
+    impl Actor for Supervisor {
+        type Msg = SupervisorMsg;
+
+        fn started(&mut self, ctx: &mut Context<Self>) {
+            self.events.lock().unwrap().push("supervisor started".to_string());
+            for _ in 0..2 {
+                let events = Arc::clone(&self.events);
+                self.workers.push(ctx.spawn(move || Worker { events: Arc::clone(&events) }));
+            }
+            assert!(ctx.register("supervisor"));
+        }
+
+        fn handle(&mut self, msg: SupervisorMsg, _ctx: &mut Context<Self>) {
+            match msg {
+                SupervisorMsg::Workers(reply) => reply.send(self.workers.iter().map(Addr::id).collect()),
+                SupervisorMsg::FailWorker => self.workers[0].tell(CounterMsg::Fail).unwrap()
+            }
+        }
+
+        fn child_stopped(&mut self, child: ActorId, failure: Option<&str>, _ctx: &mut Context<Self>) {
+            self.workers.retain(|worker| worker.id() != child);
+            self.events.lock().unwrap().push(format!("child failed: {}", failure.unwrap()));
+            let _ = self.failed.send(child);
+        }
+
+        fn stopping(&mut self, _ctx: &mut Context<Self>) {
+            self.events.lock().unwrap().push("supervisor stopping".to_string());
+        }
+
+        fn stopped(&mut self) {
+            self.events.lock().unwrap().push("supervisor stopped".to_string());
+        }
+
+        fn supervise(_child: ActorId, _failure: &str) -> SupervisionPolicy {
+            SupervisionPolicy::Stop
+        }
+    }
+
+    #[test]
+    fn supervision_tree_and_registry() {
+        let events: Events = Arc::default();
+        let system = System::new();
+        let supervisor_events = Arc::clone(&events);
+        let (failed, failures) = mpsc::channel();
+        let supervisor = system.spawn(move || Supervisor { events: Arc::clone(&supervisor_events), workers: vec![], failed: failed.clone() });
+        let workers = supervisor.ask(SupervisorMsg::Workers).unwrap();
+        assert_eq!(workers.len(), 2);
+
+        let found = system.lookup::<Supervisor>("supervisor").unwrap();
+        assert_eq!(found.id(), supervisor.id());
+        assert!(system.lookup::<Counter>("supervisor").is_none());
+        assert!(!system.register("supervisor", &supervisor));
+        // Il nome è già in uso, quindi l'attore non viene nemmeno creato
+        assert!(system.spawn_named("supervisor", || -> Counter { unreachable!() }).is_none());
+        let named = system.spawn_named("counter", Counter::default).unwrap();
+        assert_eq!(system.lookup::<Counter>("counter").unwrap().id(), named.id());
+
+        // Il genitore ha deciso Stop: il figlio che fallisce viene fermato e segnalato
+        supervisor.tell(SupervisorMsg::FailWorker).unwrap();
+        assert_eq!(failures.recv_timeout(Duration::from_secs(1)), Ok(workers[0]));
+        assert_eq!(supervisor.ask(SupervisorMsg::Workers).unwrap(), vec![workers[1]]);
+
+        // I figli terminano prima del genitore e il nome viene liberato
+        drop(system);
+        assert_eq!(*events.lock().unwrap(), vec![
+            "supervisor started",
+            "worker stopped",
+            "child failed: worker failed",
+            "supervisor stopping",
+            "worker stopped",
+            "supervisor stopped"
+        ]);
+        assert_eq!(supervisor.ask(SupervisorMsg::Workers), Err(AskError::Stopped));
+    }
+
+    #[test]
+    fn stopping_parent_on_single_worker() {
+        // Il genitore e i figli condividono l'unico worker: il genitore non può attenderli
+        // bloccandolo
+        let executor = Executor::new(1, 4);
+        let events: Events = Arc::default();
+        let system = System::with_executor(&executor);
+        let supervisor_events = Arc::clone(&events);
+        let (failed, _failures) = mpsc::channel();
+        let supervisor = system.spawn(move || Supervisor { events: Arc::clone(&supervisor_events), workers: vec![], failed: failed.clone() });
+        assert_eq!(supervisor.ask(SupervisorMsg::Workers).unwrap().len(), 2);
+        supervisor.stop();
+        drop(system);
+        assert_eq!(*events.lock().unwrap(), vec![
+            "supervisor started",
+            "supervisor stopping",
+            "worker stopped",
+            "worker stopped",
+            "supervisor stopped"
+        ]);
+    }
+
+    #[test]
+    fn panicking_stopping_still_stops_children() {
+        struct Fragile {
+            events: Events
+        }
+
+        impl Actor for Fragile {
+            type Msg = ();
+
+            fn started(&mut self, ctx: &mut Context<Self>) {
+                let events = Arc::clone(&self.events);
+                ctx.spawn(move || Worker { events: Arc::clone(&events) });
+            }
+
+            fn handle(&mut self, _msg: (), _ctx: &mut Context<Self>) {}
+
+            fn stopping(&mut self, _ctx: &mut Context<Self>) {
+                panic!("stopping failed");
+            }
+
+            fn stopped(&mut self) {
+                self.events.lock().unwrap().push("fragile stopped".to_string());
+            }
+        }
+
+        let events: Events = Arc::default();
+        let system = System::new();
+        let fragile_events = Arc::clone(&events);
+        let fragile = system.spawn(move || Fragile { events: Arc::clone(&fragile_events) });
+        fragile.tell(()).unwrap();
+        // Senza supervisione di stopping il figlio non verrebbe fermato e il drop non terminerebbe
+        drop(system);
+        assert_eq!(*events.lock().unwrap(), vec!["worker stopped", "fragile stopped"]);
+    }
+
+    #[test]
+    fn executor_delayed_messages_and_actors() {
+        let executor = Executor::new(2, 4);
+        let start = Instant::now();
+        let looper = Looper::with_state_on(&executor, vec![], move |seen: &mut Vec<(&str, Duration)>, msg| seen.push((msg, start.elapsed())), |seen| seen);
+        looper.send_delayed("late", Duration::from_millis(40)).unwrap();
+        looper.send("now").unwrap();
+        sleep(Duration::from_millis(80));
+        let seen = looper.join();
+        assert_eq!(seen.iter().map(|(msg, _)| *msg).collect::<Vec<_>>(), vec!["now", "late"]);
+        assert!(seen[1].1 >= Duration::from_millis(40));
+
+        let system = System::with_executor(&executor);
+        let counter = system.spawn(Counter::default);
+        counter.tell(CounterMsg::Add(4)).unwrap();
+        assert_eq!(counter.ask(CounterMsg::Get), Ok(4));
+        counter.tell(CounterMsg::Fail).unwrap();
+        assert_eq!(counter.ask(CounterMsg::Get), Ok(0));
+    }
+
+    #[test]
+    fn ask_future() {
+        struct Doubler;
+
+        impl Actor for Doubler {
+            type Msg = (u32, Reply<u32>);
+
+            fn handle(&mut self, (value, reply): Self::Msg, _ctx: &mut Context<Self>) {
+                sleep(Duration::from_millis(5));
+                reply.send(value * 2);
+            }
+        }
+
+        let system = System::new();
+        let doubler = system.spawn(|| Doubler);
+        let futures = (1..=3).map(|value| doubler.ask_future(move |reply| (value, reply)).unwrap()).collect();
+        assert_eq!(join_all(futures).get(), Ok(vec![2, 4, 6]));
+        assert_eq!(doubler.ask_timeout(|reply| (1, reply), Duration::from_millis(1)), Err(AskError::Timeout));
+    }
+}
@@ -0,0 +1,305 @@
+// Executor: pool di thread su cui vengono eseguiti molti Looper senza un thread per ciascuno.
+use std::collections::{BTreeMap, VecDeque};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Condvar, Mutex, Weak};
+use std::thread;
+use std::time::Instant;
+use crate::{Handler, Shared};
+use soluzione_temi_malnati::future::Promise;
+
+// Un Looper eseguito da un Executor
+#[allow(dead_code)]
+pub trait Task: Send + Sync {
+    // Vero mentre il Looper è in coda o in esecuzione su un worker: garantisce che i suoi
+    // messaggi siano elaborati da un solo worker alla volta, quindi in ordine
+    fn scheduled(&self) -> &AtomicBool;
+
+    // Elabora al più budget messaggi
+    fn run(self: Arc<Self>, budget: usize);
+}
+
+#[allow(dead_code)]
+pub struct Pooled<S, Msg, R> {
+    pub shared: Arc<Shared<Msg>>,
+    pub scheduled: AtomicBool,
+    pub handler: Mutex<Option<Handler<S, Msg, R>>>,
+    // Completato con il risultato di cleanup
+    pub result: Mutex<Option<Promise<R>>>,
+    pub executor: Arc<ExecutorShared>,
+    // Timer registrato per il primo messaggio ritardato, al più uno per Looper
+    pub timer: Mutex<Option<TimerKey>>
+}
+
+impl<S: Send + 'static, Msg: Send + 'static, R: Send + 'static> Task for Pooled<S, Msg, R> {
+    fn scheduled(&self) -> &AtomicBool {
+        &self.scheduled
+    }
+
+    fn run(self: Arc<Self>, budget: usize) {
+        let mut handler = self.handler.lock().unwrap();
+        let Some(running) = handler.as_mut() else { return };
+        for _ in 0..budget {
+            let Some(msg) = self.shared.try_recv() else { break };
+            if !running.handle(msg, &self.shared) {
+                break;
+            }
+        }
+
+        let queue = self.shared.queue.lock().unwrap();
+        if !queue.ready.is_empty() || queue.delayed.first_key_value().is_some_and(|(&(due, _), _)| due <= Instant::now()) {
+            // Budget esaurito: torna in fondo alla coda così gli altri Looper non restano fermi
+            drop(queue);
+            drop(handler);
+            let executor = Arc::clone(&self.executor);
+            executor.push(self);
+        } else if queue.closed {
+            drop(queue);
+            if let Some(key) = self.timer.lock().unwrap().take() {
+                self.executor.cancel_timer(key);
+            }
+            let finished = handler.take().unwrap();
+            if let Some(result) = self.result.lock().unwrap().take() {
+                result.complete_with(|| finished.finish());
+            }
+            self.executor.finished();
+        } else {
+            // Il flag viene azzerato sotto il lock della coda: un send successivo vede il flag
+            // azzerato e rimette il Looper in coda
+            self.scheduled.store(false, Ordering::SeqCst);
+            if let Some(due) = queue.next_due() {
+                let task: Weak<dyn Task> = Arc::downgrade(&self) as Weak<Self>;
+                let mut timer = self.timer.lock().unwrap();
+                *timer = Some(self.executor.set_timer(*timer, due, task));
+            }
+        }
+    }
+}
+
+// Scadenza di un timer e id che distingue timer con la stessa scadenza
+pub type TimerKey = (Instant, u64);
+
+#[allow(dead_code)]
+pub struct ExecutorState {
+    runnable: VecDeque<Arc<dyn Task>>,
+    // Looper con messaggi ritardati, da rimettere in coda alla scadenza
+    timers: BTreeMap<TimerKey, Weak<dyn Task>>,
+    next_timer: u64,
+    // Looper non ancora terminati
+    pub loopers: usize,
+    shutdown: bool
+}
+
+#[allow(dead_code)]
+pub struct ExecutorShared {
+    pub state: Mutex<ExecutorState>,
+    condvar: Condvar,
+    // Numero massimo di messaggi elaborati per turno da un Looper
+    budget: usize
+}
+
+#[allow(dead_code)]
+impl ExecutorShared {
+    fn push(&self, task: Arc<dyn Task>) {
+        self.state.lock().unwrap().runnable.push_back(task);
+        self.condvar.notify_one();
+    }
+
+    pub fn schedule(&self, task: Arc<dyn Task>) {
+        if !task.scheduled().swap(true, Ordering::SeqCst) {
+            self.push(task);
+        }
+    }
+
+    // Sostituisce il timer previous di un Looper con uno alla scadenza due. Se previous ha già
+    // quella scadenza e non è ancora scattato resta valido, così un Looper che riceve molti
+    // messaggi mentre ne attende uno ritardato non accumula timer
+    fn set_timer(&self, previous: Option<TimerKey>, due: Instant, task: Weak<dyn Task>) -> TimerKey {
+        let mut state = self.state.lock().unwrap();
+        if let Some(previous) = previous {
+            if previous.0 == due && state.timers.contains_key(&previous) {
+                return previous;
+            }
+            state.timers.remove(&previous);
+        }
+        let key = (due, state.next_timer);
+        state.next_timer += 1;
+        state.timers.insert(key, task);
+        self.condvar.notify_one();
+        key
+    }
+
+    fn cancel_timer(&self, key: TimerKey) {
+        self.state.lock().unwrap().timers.remove(&key);
+    }
+
+    fn finished(&self) {
+        self.state.lock().unwrap().loopers -= 1;
+        self.condvar.notify_all();
+    }
+
+    fn work(&self) {
+        let mut state = self.state.lock().unwrap();
+        loop {
+            let now = Instant::now();
+            while let Some(entry) = state.timers.first_entry() {
+                if entry.key().0 > now {
+                    break;
+                }
+                if let Some(task) = entry.remove().upgrade() {
+                    if !task.scheduled().swap(true, Ordering::SeqCst) {
+                        state.runnable.push_back(task);
+                    }
+                }
+            }
+            if let Some(task) = state.runnable.pop_front() {
+                drop(state);
+                task.run(self.budget);
+                state = self.state.lock().unwrap();
+                continue;
+            }
+            if state.shutdown && state.loopers == 0 {
+                return;
+            }
+            state = match state.timers.keys().next() {
+                Some(&(due, _)) => self.condvar.wait_timeout(state, due.saturating_duration_since(now)).unwrap().0,
+                None => self.condvar.wait(state).unwrap()
+            };
+        }
+    }
+}
+
+// Pool di thread condiviso da molti Looper: ogni Looper elabora i propri messaggi in ordine e
+// uno alla volta, ma su un worker qualsiasi. Il drop attende la terminazione di tutti i Looper
+// creati con l'Executor, che quindi vanno distrutti prima
+#[allow(dead_code)]
+pub struct Executor {
+    pub shared: Arc<ExecutorShared>,
+    workers: Vec<thread::JoinHandle<()>>
+}
+
+#[allow(dead_code)]
+impl Executor {
+    // budget è il numero massimo di messaggi che un Looper elabora prima di cedere il worker
+    pub fn new(threads: usize, budget: usize) -> Executor {
+        assert!(threads > 0 && budget > 0, "servono almeno un thread e un messaggio per turno");
+        let shared = Arc::new(ExecutorShared {
+            state: Mutex::new(ExecutorState {
+                runnable: VecDeque::new(),
+                timers: BTreeMap::new(),
+                next_timer: 0,
+                loopers: 0,
+                shutdown: false
+            }),
+            condvar: Condvar::new(),
+            budget
+        });
+        let workers = (0..threads).map(|_| {
+            let shared = Arc::clone(&shared);
+            thread::spawn(move || shared.work())
+        }).collect();
+        Executor { shared, workers }
+    }
+}
+
+impl Drop for Executor {
+    fn drop(&mut self) {
+        self.shared.state.lock().unwrap().shutdown = true;
+        self.shared.condvar.notify_all();
+        for worker in self.workers.drain(..) {
+            worker.join().unwrap();
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Executor;
+    use crate::Looper;
+    use std::sync::{mpsc, Arc, Mutex};
+    use std::thread::{self, sleep};
+    use std::time::Duration;
+
+    #[test]
+    fn executor_keeps_per_looper_order() {
+        let executor = Executor::new(4, 8);
+        let loopers: Vec<_> = (0..200).map(|_| {
+            Looper::with_state_on(&executor, vec![], |seen: &mut Vec<u32>, msg| seen.push(msg), |seen| seen)
+        }).collect();
+        for msg in 0..50 {
+            for looper in &loopers {
+                looper.send(msg).unwrap();
+            }
+        }
+        for looper in loopers {
+            assert_eq!(looper.join(), (0..50).collect::<Vec<_>>());
+        }
+    }
+
+    #[test]
+    fn delayed_message_arms_a_single_timer() {
+        let executor = Executor::new(2, 4);
+        let looper = Looper::on(&executor, |_: u32| {}, || {});
+        looper.send_delayed(0, Duration::from_secs(60)).unwrap();
+        for msg in 1..=20 {
+            looper.send(msg).unwrap();
+            sleep(Duration::from_millis(1));
+        }
+        sleep(Duration::from_millis(10));
+        assert_eq!(executor.shared.state.lock().unwrap().timers.len(), 1);
+        drop(looper);
+        assert!(executor.shared.state.lock().unwrap().timers.is_empty());
+    }
+
+    #[test]
+    fn executor_budget_is_fair() {
+        let executor = Executor::new(1, 2);
+        let order = Arc::new(Mutex::new(vec![]));
+        let (unblock, blocked) = mpsc::channel::<()>();
+        // Occupa l'unico worker mentre vengono inviati i messaggi
+        let gate = Looper::on(&executor, move |_: ()| blocked.recv().unwrap(), || {});
+        gate.send(()).unwrap();
+        sleep(Duration::from_millis(20));
+
+        let log = Arc::clone(&order);
+        let busy = Looper::on(&executor, move |msg: u32| log.lock().unwrap().push(format!("busy {}", msg)), || {});
+        let log = Arc::clone(&order);
+        let quiet = Looper::on(&executor, move |msg: u32| log.lock().unwrap().push(format!("quiet {}", msg)), || {});
+        for msg in 1..=6 {
+            busy.send(msg).unwrap();
+        }
+        quiet.send(1).unwrap();
+        quiet.send(2).unwrap();
+        unblock.send(()).unwrap();
+        busy.join();
+        quiet.join();
+        gate.join();
+        assert_eq!(*order.lock().unwrap(), vec![
+            "busy 1", "busy 2", "quiet 1", "quiet 2", "busy 3", "busy 4", "busy 5", "busy 6"
+        ]);
+    }
+
+    #[test]
+    fn close_discards_delayed_messages_not_yet_due() {
+        let executor = Executor::new(1, 8);
+        let payload = Arc::new(());
+        let (unblock, blocked) = mpsc::channel();
+        let blocked = Mutex::new(blocked);
+        let looper = Looper::with_state_on(&executor, vec![], move |seen: &mut Vec<&str>, (msg, _): (&str, Arc<()>)| {
+            if msg == "block" {
+                blocked.lock().unwrap().recv().unwrap();
+            }
+            seen.push(msg);
+        }, |seen| seen);
+        looper.send(("block", Arc::clone(&payload))).unwrap();
+        looper.send_delayed(("due", Arc::clone(&payload)), Duration::from_millis(10)).unwrap();
+        looper.send_delayed(("late", Arc::clone(&payload)), Duration::from_millis(200)).unwrap();
+        sleep(Duration::from_millis(30));
+        let closing = thread::spawn(move || looper.join());
+        // Alla chiusura "late" non è ancora scaduto, e non viene elaborato anche se lo è quando
+        // la coda viene smaltita
+        sleep(Duration::from_millis(250));
+        unblock.send(()).unwrap();
+        assert_eq!(closing.join().unwrap(), vec!["block", "due"]);
+        assert_eq!(Arc::strong_count(&payload), 1);
+    }
+}
@@ -0,0 +1,898 @@
+use std::fmt::Debug;
+//Domanda 1: Si definisca il concetto di smart pointer, quindi si fornisca un esempio (Rust o C++)
+//che ne evidenzi il ciclo di vita.
+//
+//Uno smart pointer è genericamente una struttura dati che avvolge un puntatore ad un dato.
+//Mentre i normali puntatori normali rappresentano effettivamente la corrispondenza tra il dato e
+//il suo indirizzo in memoria e qualsiasi operazione su di essi è consentita (con i conseguenti
+//problemi), gli smart pointer si occupano di fornire al programmatore un set limitato di opzioni
+//di esecuzione in modo che in grandi codebase la scrittura di codice da parte di persone
+//differenti mantenga sintatticamente delle proprietà esplicite e riduca la possibilità di
+//commettere errori conoscendo lo scopo per cui il puntatore è stato creato. In C++ gli smart
+//pointers sono stati introdotti nella versione del linguaggio C++11, tra i primi smart pointers
+//introdotti abbiamo unique_ptr e shared_ptr. Questi puntatori limitano la compilazione del codice
+//alla presenza per un dato di un unico puntatore alla volta, quando lo scopo di un unique_ptr
+//termina è possibile crearne uno nuovo o di uno shared_ptr che ad esempio permette di avere
+//funzionalità analoghe ai puntatori nativi del linguaggio.
+//In Rust invece data la filosofia del linguaggio orientata a limitare gli errori prima ancora che
+//un programma venga compilato esistono diverse varietà di smart pointer ognuno orientato ad uno
+//scopo diverso. Tra gli smart pointers più comuni abbiamo: Box<T>, Rc<T>, Weak<T>, Cell<T>, RefCell<T>,
+//Cow<T> e Arc<T>.
+//Lo smart pointer Box<T> ad esempio mira ad allocare un dato specifico sullo heap piuttosto che
+//sullo stack, le motivazioni e quindi le funzionalità che mira ad offrire sono la sopravvivenza
+//allo scopo in cui il dato viene creato e la corretta deallocazione del dato stesso al termine del
+//programma. Molto spesso il tipo Box<T> viene usato in coppia con le virtual table che vengono
+//create in fase di esecuzione per poter contenere dati di una specifico tipo riferendosi ad essi
+//tramite tratti che implementano. Questo consente di implementare tramite Box meccanismi
+//corrispondenti in altri linguaggi a comportamenti ereditari piuttosto che polimorfici, potendo
+//cosi' raggruppare dati di diverso tipo indicando che i loro contenitori sono collezioni di
+//Box<tratto comune dei dati>.
+//
+//Domanda 2: Si illustrino le differenze nel linguaggio Rust tra std::channel() e
+//std::sync_channel(), indicando quali tipi di sincronizzazione i due meccanismi permettono.
+//
+//In rust la sincronizzazione tra thread differenti può avvenire tramite l'uso di meccanismi basati
+//su messaggi. Ogni canale di comunicazione per l'invio dei messaggi è unidirezionale e viene
+//creato con una delle due funzioni sopracitate. Alla creazione queste funzioni ritornano una
+//struttura dati rappresentante il mittente e il destinatario per il canale, sotto forma di
+//strutture rust Sender e Receiver. Queste implementano i metodi che verranno effettivamente usati
+//per la comunicazione. Il Sender deve essere trasferito al thread che invierà i messaggi mentre il
+//Receiver non può essere "mosso" dal thread che invoca la funzione di creazione del canale.
+//La differenza sostanziale tra i due metodi di creazione dei canali sta nella capacità
+//dei canali stessi che in qualche modo influenza la frequenza con cui mittente e
+//destinatario dovranno interagire prima di comunicare nuovamente. Mentre la funzione channel()
+//consente di conservare un numero illimitato di messaggi e quindi consente al Sender di inviare
+//messaggi senza preoccuparsi che il destinatario li abbia ricevuti, la funzione sync_channel()
+//richiede alla creazione di passarvi un parametro numerico che indicherà la capacità del canale.
+//Quando il sender nel secondo caso invia un nuovo messaggio superando la capacità del canale
+//specificata, esso entra in attesa che il canale si svuoti e che quindi data la capacità n che
+//almeno un messaggio venga letto ogni n mandati. E' possibile implementare un canale di rendevouz
+//passando a sync_channel() come parametro 0. Facendo ciò il sender prima di inviare ogni messaggio
+//deve controllare che il messaggio precedente sia stato letto, altrimenti entra in blocco fino a
+//che ciò non accade.
+//
+//Domanda 3: Dato il seguente codice Rust (ogni linea è preceduta dal suo indice) si descriva il
+//contenuto dello stack e dello heap al termine dell'esecuzione della riga 15:
+//
+//"
+// 1. struct Point { 
+// 2. x: i16, 
+// 3. y: i16,
+// 4. }
+// 5.
+// 6. enum PathCommand { 
+// 7. Move(Point), 
+// 8. Line(Point),
+// 9. Close,
+// 10. }
+// 11. let mut v = Vec::<PathCommand>::new();
+// 12. v.push(PathCommand::Move(Point{x:1,y:1 }));
+// 13. v.push(PathCommand::Line(Point{x:10, y:20}));
+// 14. v.push(PathCommand::C/ose);
+// 15. let slice = &v[ .. ];
+//"
+//
+// Le definizioni della struct Point e dell'enum PathCommand non occupano memoria ma indicano
+// quanta memoria ognuna delle instanze dei dati stessi occuperanno ovvero entrambe 4byte.
+// 
+// All'esecuzione della riga 11, il vettore viene allocato e sullo heap vengono allocati 8byte
+// rappresentanti con i primi 4 la dimensione attuale del vettore che sarà quindi 0 e il puntatore
+// all'indirizzo del primo elemento, che sarà inizialmente non settato quindi la memoria nei
+// secondi 4 byte manterrà il valore che aveva prima dell'esecuzione del programma presentato.
+// Nello stack invece verrà allocata la variabile v contenente l'indirizzo della struttura sullo
+// heap e quindi occuperà 4 byte.
+// Heap: 8 byte.
+// Stack: 4 byte.
+//
+// All'esecuzione della riga 12, il vettore viene esteso con un elemento di PathCommand. La memoria
+// dello heap cambia nel contenuto facendo puntare i secondi 4 byte del blocco al nuovo elemento
+// aggiunto e cresce in dimensione di 4 byte per l'allocazione del nuovo elemento. Lo stack rimane
+// invariato.
+//
+// Heap: 12 byte.
+// Stack: 4 byte.
+//
+// All'esecuzione delle righe 13 e 14 si ripete lo stesso procedimento di 12 e quindi lo heap
+// cresce ad ognuna delle esecuzioni di 4 byte e lo stack rimane invariato.
+//
+// Heap: 20 byte.
+// Stack: 4 byte.
+//
+// All'esecuzione della riga 15, viene creata una slice del vettore precedentemente allocato il che
+// implica la creazione di un puntatore sullo stack al vettore occupando 4 byte.
+// Heap: 20 byte.
+// Stack: 8 byte.
+//
+//
+// Domanda 4: Un paradigma frequentemente usato nei sistemi reattivi e costituito dall'astrazione detta Looper. 
+// Quando viene creato, un Looper crea una coda di oggetti generici di tipo Message ed un thread. 
+// II thread attende - senza consumare cicli di CPU - che siano presenti messaggi nella coda, Ii 
+// estrae a uno a uno nell'ordine di arrivo, e li elabora. II costruttore di Looper riceve due parametri, 
+// entrambi di tipo (puntatore a) funzione: process( ... ) e cleanup(). La prim a e una funzione
+// responsabile di elaborare i singoli messaggi ricevuti attraverso la coda; tale funzione accetta un
+// unico parametro in ingresso di tipo Message e non ritorna nulla; La seconda e funzione priva di 
+// argomenti e valore di ritorno e verra invocata dal thread incapsulato nel Looper quando esso 
+// stara per terminare. 
+// Looper offre un unico metodo pubblico, thread safe, oltre a quelli di servizio, necessari per 
+// gestirne ii ciclo di vita: send(msg), che accetta come parametro un oggetto generico di tipo 
+// Message che verra inserito nella coda e successivamente estratto dal thread ed inoltrato alla 
+// funzione di elaborazione. Quando un oggetto Looper viene distrutto, occorre fare in modo che ii 
+// thread contenuto al suo interno invochi la seconda funzione passata nel costruttore e poi termini. 
+// Si implementi, utilizzando ii linguaggio Rust o C++, tale astrazione tenendo canto che i suoi metodi 
+// dovranno essere thread-safe.
+//
+
+mod actor;
+mod executor;
+mod pool;
+
+use std::any::Any;
+use std::collections::{BTreeMap, VecDeque};
+use std::fmt;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::AtomicBool;
+use std::sync::{Arc, Condvar, Mutex, OnceLock};
+use std::thread::{self, sleep};
+use std::time::{Duration, Instant};
+use executor::{Executor, ExecutorShared, Pooled, Task};
+use soluzione_temi_malnati::future::{Future, FutureError, Promise};
+use soluzione_temi_malnati::panic_message;
+
+#[derive(Debug)]
+struct Stopped;
+
+impl fmt::Display for Stopped {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "the looper thread has stopped")
+    }
+}
+
+impl std::error::Error for Stopped {}
+
+// Il messaggio non inviato viene restituito al chiamante
+#[derive(Debug, PartialEq)]
+enum TrySendError<Msg> {
+    Full(Msg),
+    Stopped(Msg)
+}
+
+#[derive(Debug, PartialEq)]
+#[allow(dead_code)]
+enum SendTimeoutError<Msg> {
+    Timeout(Msg),
+    Stopped(Msg)
+}
+
+impl<Msg> fmt::Display for TrySendError<Msg> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TrySendError::Full(_) => write!(f, "the looper queue is full"),
+            TrySendError::Stopped(_) => write!(f, "the looper thread has stopped")
+        }
+    }
+}
+
+impl<Msg: fmt::Debug> std::error::Error for TrySendError<Msg> {}
+
+impl<Msg> fmt::Display for SendTimeoutError<Msg> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SendTimeoutError::Timeout(_) => write!(f, "the looper queue is still full after the timeout"),
+            SendTimeoutError::Stopped(_) => write!(f, "the looper thread has stopped")
+        }
+    }
+}
+
+impl<Msg: fmt::Debug> std::error::Error for SendTimeoutError<Msg> {}
+
+// Quanto attendere quando la coda limitata è piena
+#[derive(Clone, Copy)]
+#[allow(dead_code)]
+enum Wait {
+    Never,
+    Until(Instant),
+    Forever
+}
+
+// Cosa fare quando process va in panic elaborando un messaggio
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[allow(dead_code)]
+enum SupervisionPolicy {
+    // Il messaggio viene scartato e si prosegue con lo stesso stato
+    Skip,
+    // Il messaggio viene scartato e lo stato viene ricreato da capo
+    Restart,
+    // I messaggi in coda vengono scartati, i successivi send falliscono e viene chiamata cleanup
+    Stop
+}
+
+// Panic avvenuto durante l'elaborazione di un messaggio, passato alla funzione on_panic
+struct PanicReport {
+    payload: Box<dyn Any + Send>,
+    policy: SupervisionPolicy
+}
+
+impl PanicReport {
+    // Il messaggio passato a panic!, se è una stringa
+    fn message(&self) -> Option<&str> {
+        panic_message(&*self.payload)
+    }
+}
+
+impl fmt::Debug for PanicReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PanicReport").field("message", &self.message()).field("policy", &self.policy).finish()
+    }
+}
+
+struct Queue<Msg> {
+    // Messaggi da elaborare subito, nell'ordine di arrivo
+    ready: VecDeque<Msg>,
+    // Messaggi ritardati, ordinati per scadenza e a parità di scadenza per ordine di invio
+    delayed: BTreeMap<(Instant, u64), Msg>,
+    next_id: u64,
+    // Numero massimo di messaggi in attesa, compresi quelli ritardati
+    capacity: Option<usize>,
+    closed: bool
+}
+
+impl<Msg> Queue<Msg> {
+    // Sposta in coda i messaggi ritardati già scaduti
+    fn promote(&mut self, now: Instant) {
+        while let Some(entry) = self.delayed.first_entry() {
+            if entry.key().0 > now {
+                break;
+            }
+            self.ready.push_back(entry.remove());
+        }
+    }
+
+    fn next_due(&self) -> Option<Instant> {
+        self.delayed.keys().next().map(|&(due, _)| due)
+    }
+
+    fn len(&self) -> usize {
+        self.ready.len() + self.delayed.len()
+    }
+
+    fn is_full(&self) -> bool {
+        self.capacity.is_some_and(|capacity| self.len() >= capacity)
+    }
+}
+
+struct Shared<Msg> {
+    queue: Mutex<Queue<Msg>>,
+    // Segnalata ad ogni nuovo messaggio e alla chiusura
+    available: Condvar,
+    // Segnalata quando si libera spazio in una coda limitata e alla chiusura
+    space: Condvar,
+    // Per un Looper eseguito da un Executor: lo mette in coda tra quelli da eseguire
+    wake: OnceLock<Box<dyn Fn() + Send + Sync>>
+}
+
+#[allow(dead_code)]
+impl<Msg> Shared<Msg> {
+    fn new(capacity: Option<usize>) -> Self {
+        assert!(capacity != Some(0), "la capacità deve essere positiva");
+        Shared {
+            queue: Mutex::new(Queue {
+                ready: VecDeque::new(),
+                delayed: BTreeMap::new(),
+                next_id: 0,
+                capacity,
+                closed: false
+            }),
+            available: Condvar::new(),
+            space: Condvar::new(),
+            wake: OnceLock::new()
+        }
+    }
+
+    fn wake(&self) {
+        if let Some(wake) = self.wake.get() {
+            wake();
+        }
+    }
+
+    // Attende senza consumare CPU un nuovo messaggio o la scadenza del prossimo messaggio
+    // ritardato. Dopo la chiusura restituisce i messaggi già in coda e poi None
+    fn recv(&self) -> Option<Msg> {
+        let mut queue = self.queue.lock().unwrap();
+        loop {
+            queue.promote(Instant::now());
+            if let Some(msg) = queue.ready.pop_front() {
+                self.space.notify_one();
+                return Some(msg);
+            }
+            if queue.closed {
+                return None;
+            }
+            queue = match queue.next_due() {
+                Some(due) => self.available.wait_timeout(queue, due.saturating_duration_since(Instant::now())).unwrap().0,
+                None => self.available.wait(queue).unwrap()
+            };
+        }
+    }
+
+    fn try_recv(&self) -> Option<Msg> {
+        let mut queue = self.queue.lock().unwrap();
+        queue.promote(Instant::now());
+        let msg = queue.ready.pop_front();
+        if msg.is_some() {
+            self.space.notify_one();
+        }
+        msg
+    }
+
+    // Se la coda è piena attende secondo wait, poi inserisce msg con insert
+    fn push(&self, msg: Msg, wait: Wait, insert: impl FnOnce(&mut Queue<Msg>, Msg)) -> Result<(), TrySendError<Msg>> {
+        let mut queue = self.queue.lock().unwrap();
+        loop {
+            if queue.closed {
+                return Err(TrySendError::Stopped(msg));
+            }
+            if !queue.is_full() {
+                break;
+            }
+            queue = match wait {
+                Wait::Never => return Err(TrySendError::Full(msg)),
+                Wait::Until(deadline) => {
+                    let now = Instant::now();
+                    if now >= deadline {
+                        return Err(TrySendError::Full(msg));
+                    }
+                    self.space.wait_timeout(queue, deadline - now).unwrap().0
+                },
+                Wait::Forever => self.space.wait(queue).unwrap()
+            };
+        }
+        insert(&mut queue, msg);
+        self.available.notify_one();
+        drop(queue);
+        self.wake();
+        Ok(())
+    }
+
+    fn enqueue(&self, msg: Msg, insert: impl FnOnce(&mut Queue<Msg>, Msg)) -> Result<(), Box<dyn std::error::Error>> {
+        self.push(msg, Wait::Forever, insert).map_err(|_| Box::new(Stopped) as Box<dyn std::error::Error>)
+    }
+
+    // Dopo la chiusura il thread elabora i messaggi già in coda e termina. I messaggi ritardati
+    // già scaduti vengono elaborati, gli altri vengono scartati subito: altrimenti verrebbero
+    // elaborati o meno a seconda di quanto tempo serve a smaltire la coda
+    fn close(&self) {
+        let mut queue = self.queue.lock().unwrap();
+        queue.promote(Instant::now());
+        queue.delayed.clear();
+        queue.closed = true;
+        drop(queue);
+        self.available.notify_all();
+        self.space.notify_all();
+        self.wake();
+    }
+
+    // Chiude la coda scartando i messaggi in attesa
+    fn abort(&self) {
+        let mut queue = self.queue.lock().unwrap();
+        queue.closed = true;
+        queue.ready.clear();
+        queue.delayed.clear();
+        self.available.notify_all();
+        self.space.notify_all();
+        drop(queue);
+        self.wake();
+    }
+}
+
+type Process<S, Msg> = Box<dyn FnMut(&mut S, Msg) + Send>;
+
+// Stato e funzioni di un Looper, usati da un solo thread alla volta
+struct Handler<S, Msg, R> {
+    state: S,
+    init: Box<dyn FnMut() -> S + Send>,
+    process: Process<S, Msg>,
+    cleanup: Box<dyn FnOnce(S) -> R + Send>,
+    policy: SupervisionPolicy,
+    on_panic: Box<dyn FnMut(PanicReport) + Send>
+}
+
+impl<S, Msg, R> Handler<S, Msg, R> {
+    // Restituisce false se il Looper deve fermarsi
+    fn handle(&mut self, msg: Msg, shared: &Shared<Msg>) -> bool {
+        // Il panic viene intercettato per singolo messaggio, così il thread sopravvive
+        let Err(payload) = panic::catch_unwind(AssertUnwindSafe(|| (self.process)(&mut self.state, msg))) else { return true };
+        (self.on_panic)(PanicReport { payload, policy: self.policy });
+        match self.policy {
+            SupervisionPolicy::Skip => true,
+            SupervisionPolicy::Restart => {
+                self.state = (self.init)();
+                true
+            },
+            SupervisionPolicy::Stop => {
+                shared.abort();
+                false
+            }
+        }
+    }
+
+    fn finish(self) -> R {
+        (self.cleanup)(self.state)
+    }
+}
+
+// Risultato di cleanup per un Looper eseguito da un Executor o di un job del ThreadPool
+// Propaga al chiamante il fallimento di un calcolo il cui risultato arriva tramite un Future
+fn resume_failure(error: FutureError) -> ! {
+    match error {
+        FutureError::Panicked(message) => panic::resume_unwind(Box::new(message)),
+        error => panic!("{}", error)
+    }
+}
+
+#[allow(dead_code)]
+enum Runner<R> {
+    Thread(thread::JoinHandle<R>),
+    // L'Executor conserva il Looper solo mentre è in coda, il Runner lo mantiene in vita
+    Pooled(Future<R>, Arc<dyn Task>)
+}
+
+#[allow(dead_code)]
+impl<R> Runner<R> {
+    fn wait(self) -> Result<R, FutureError> {
+        match self {
+            Runner::Thread(handle) => handle.join()
+                .map_err(|panic| FutureError::Panicked(panic_message(&*panic).unwrap_or_default().to_string())),
+            Runner::Pooled(result, _) => result.get()
+        }
+    }
+
+    fn is_finished(&self) -> bool {
+        match self {
+            Runner::Thread(handle) => handle.is_finished(),
+            Runner::Pooled(result, _) => result.is_done()
+        }
+    }
+}
+
+// R è il risultato di cleanup, recuperabile con join
+struct Looper<Msg: Send, R = ()> {
+    shared: Arc<Shared<Msg>>,
+    runner: Option<Runner<R>>
+}
+
+#[allow(dead_code)]
+impl<Msg: Send + 'static> Looper<Msg> {
+    fn new(mut process: impl FnMut(Msg) + Send + 'static, cleanup: impl FnOnce() + Send + 'static) -> Looper<Msg> {
+        Looper::with_state((), move |_, msg| process(msg), move |_| cleanup())
+    }
+
+    fn bounded(capacity: usize, mut process: impl FnMut(Msg) + Send + 'static, cleanup: impl FnOnce() + Send + 'static) -> Looper<Msg> {
+        Looper::bounded_with_state(capacity, (), move |_, msg| process(msg), move |_| cleanup())
+    }
+
+    // Il Looper non ha un proprio thread ma viene eseguito dai worker di executor
+    fn on(executor: &Executor, mut process: impl FnMut(Msg) + Send + 'static, cleanup: impl FnOnce() + Send + 'static) -> Looper<Msg> {
+        Looper::with_state_on(executor, (), move |_, msg| process(msg), move |_| cleanup())
+    }
+}
+
+#[allow(dead_code)]
+impl<Msg: Send + 'static, R: Send + 'static> Looper<Msg, R> {
+    // Lo stato init è posseduto dal thread del Looper: process lo modifica ad ogni messaggio e
+    // cleanup lo consuma producendo il risultato. Un messaggio che manda in panic process viene
+    // scartato
+    fn with_state<S: Send + 'static>(init: S, process: impl FnMut(&mut S, Msg) + Send + 'static, cleanup: impl FnOnce(S) -> R + Send + 'static) -> Looper<Msg, R> {
+        Looper::with_state_in(Shared::new(None), init, process, cleanup)
+    }
+
+    // Al più capacity messaggi in attesa: send blocca il chiamante finché non si libera spazio.
+    // Inviare a una coda piena dal thread del Looper stesso causa uno stallo
+    fn bounded_with_state<S: Send + 'static>(capacity: usize, init: S, process: impl FnMut(&mut S, Msg) + Send + 'static, cleanup: impl FnOnce(S) -> R + Send + 'static) -> Looper<Msg, R> {
+        Looper::with_state_in(Shared::new(Some(capacity)), init, process, cleanup)
+    }
+
+    fn with_state_on<S: Send + 'static>(executor: &Executor, init: S, process: impl FnMut(&mut S, Msg) + Send + 'static, cleanup: impl FnOnce(S) -> R + Send + 'static) -> Looper<Msg, R> {
+        let mut init = Some(init);
+        let init = move || init.take().expect("con Skip lo stato viene creato una sola volta");
+        Looper::spawn(Arc::new(Shared::new(None)), Some(&executor.shared), init, process, cleanup, SupervisionPolicy::Skip, |_| {})
+    }
+
+    fn with_state_in<S: Send + 'static>(shared: Shared<Msg>, init: S, process: impl FnMut(&mut S, Msg) + Send + 'static, cleanup: impl FnOnce(S) -> R + Send + 'static) -> Looper<Msg, R> {
+        let mut init = Some(init);
+        let init = move || init.take().expect("con Skip lo stato viene creato una sola volta");
+        Looper::spawn(Arc::new(shared), None, init, process, cleanup, SupervisionPolicy::Skip, |_| {})
+    }
+
+    // init crea lo stato iniziale e quello con cui ripartire dopo un panic se policy è Restart;
+    // ogni panic viene segnalato a on_panic
+    fn supervised<S: Send + 'static>(
+        init: impl FnMut() -> S + Send + 'static,
+        process: impl FnMut(&mut S, Msg) + Send + 'static,
+        cleanup: impl FnOnce(S) -> R + Send + 'static,
+        policy: SupervisionPolicy,
+        on_panic: impl FnMut(PanicReport) + Send + 'static
+    ) -> Looper<Msg, R> {
+        Looper::spawn(Arc::new(Shared::new(None)), None, init, process, cleanup, policy, on_panic)
+    }
+
+    // La coda può essere creata prima del thread, così chi la condivide conosce già il suo
+    // indirizzo. Senza executor il Looper ha un proprio thread
+    fn spawn<S: Send + 'static>(
+        shared: Arc<Shared<Msg>>,
+        executor: Option<&Arc<ExecutorShared>>,
+        mut init: impl FnMut() -> S + Send + 'static,
+        process: impl FnMut(&mut S, Msg) + Send + 'static,
+        cleanup: impl FnOnce(S) -> R + Send + 'static,
+        policy: SupervisionPolicy,
+        on_panic: impl FnMut(PanicReport) + Send + 'static
+    ) -> Looper<Msg, R> {
+        let shared_clone = Arc::clone(&shared);
+        let runner = match executor {
+            None => Runner::Thread(thread::spawn(move || {
+                let handler = Handler {
+                    state: init(),
+                    init: Box::new(init),
+                    process: Box::new(process),
+                    cleanup: Box::new(cleanup),
+                    policy,
+                    on_panic: Box::new(on_panic)
+                };
+                Looper::start_loop(shared_clone, handler)
+            })),
+            Some(executor) => {
+                let (promise, result) = Promise::new();
+                let task = Arc::new(Pooled {
+                    shared: shared_clone,
+                    scheduled: AtomicBool::new(false),
+                    handler: Mutex::new(Some(Handler {
+                        state: init(),
+                        init: Box::new(init),
+                        process: Box::new(process),
+                        cleanup: Box::new(cleanup),
+                        policy,
+                        on_panic: Box::new(on_panic)
+                    })),
+                    result: Mutex::new(Some(promise)),
+                    executor: Arc::clone(executor),
+                    timer: Mutex::new(None)
+                });
+                executor.state.lock().unwrap().loopers += 1;
+                let weak = Arc::downgrade(&task);
+                let executor = Arc::clone(executor);
+                let _ = shared.wake.set(Box::new(move || {
+                    if let Some(task) = weak.upgrade() {
+                        executor.schedule(task);
+                    }
+                }));
+                // Messaggi inviati prima di impostare wake
+                shared.wake();
+                Runner::Pooled(result, task)
+            }
+        };
+
+        Looper {
+            shared,
+            runner: Some(runner)
+        }
+    }
+
+    // Se la coda è limitata e piena attende che si liberi spazio
+    pub fn send(&self, msg: Msg) -> Result<(), Box<dyn std::error::Error>>{
+        self.shared.enqueue(msg, |queue, msg| queue.ready.push_back(msg))
+    }
+
+    pub fn try_send(&self, msg: Msg) -> Result<(), TrySendError<Msg>> {
+        self.shared.push(msg, Wait::Never, |queue, msg| queue.ready.push_back(msg))
+    }
+
+    pub fn send_timeout(&self, msg: Msg, timeout: Duration) -> Result<(), SendTimeoutError<Msg>> {
+        self.shared.push(msg, Wait::Until(Instant::now() + timeout), |queue, msg| queue.ready.push_back(msg))
+            .map_err(|e| match e {
+                TrySendError::Full(msg) => SendTimeoutError::Timeout(msg),
+                TrySendError::Stopped(msg) => SendTimeoutError::Stopped(msg)
+            })
+    }
+
+    // request costruisce il messaggio a partire dal Promise con cui process risponderà. Se il
+    // messaggio viene scartato senza risposta il Future restituisce FutureError::Disconnected
+    pub fn request<T: Send + 'static>(&self, request: impl FnOnce(Promise<T>) -> Msg) -> Result<Future<T>, Box<dyn std::error::Error>> {
+        let (promise, future) = Promise::new();
+        self.send(request(promise))?;
+        Ok(future)
+    }
+
+    // Il messaggio viene elaborato prima di tutti quelli già in coda
+    pub fn send_front(&self, msg: Msg) -> Result<(), Box<dyn std::error::Error>> {
+        self.shared.enqueue(msg, |queue, msg| queue.ready.push_front(msg))
+    }
+
+    // Un messaggio ancora in attesa quando il Looper viene chiuso non viene elaborato
+    pub fn send_delayed(&self, msg: Msg, delay: Duration) -> Result<(), Box<dyn std::error::Error>> {
+        self.send_at(msg, Instant::now() + delay)
+    }
+
+    // Il messaggio viene messo in coda all'istante at, dopo quelli inviati prima di allora
+    pub fn send_at(&self, msg: Msg, at: Instant) -> Result<(), Box<dyn std::error::Error>> {
+        if at <= Instant::now() {
+            return self.send(msg);
+        }
+        self.shared.enqueue(msg, |queue, msg| {
+            queue.delayed.insert((at, queue.next_id), msg);
+            queue.next_id += 1;
+        })
+    }
+
+    // Scarta i messaggi in attesa, anche ritardati, per cui predicate restituisce true e ne
+    // restituisce il numero
+    pub fn remove_pending(&self, mut predicate: impl FnMut(&Msg) -> bool) -> usize {
+        let mut queue = self.shared.queue.lock().unwrap();
+        let before = queue.len();
+        queue.ready.retain(|msg| !predicate(msg));
+        queue.delayed.retain(|_, msg| !predicate(msg));
+        self.shared.space.notify_all();
+        before - queue.len()
+    }
+
+    // Messaggi in attesa di essere elaborati, compresi quelli ritardati
+    pub fn queue_len(&self) -> usize {
+        self.shared.queue.lock().unwrap().len()
+    }
+
+    // Attende che il Looper termini da sé senza chiudere la coda, per esempio dopo un abort
+    fn wait_stopped(&mut self) {
+        if let Some(runner) = self.runner.take() {
+            let _ = runner.wait();
+        }
+    }
+
+    // Come il drop, ma restituisce il risultato di cleanup
+    pub fn join(mut self) -> R {
+        self.shared.close();
+        self.runner.take().unwrap().wait().unwrap_or_else(|error| resume_failure(error))
+    }
+
+    fn start_loop<S>(shared: Arc<Shared<Msg>>, mut handler: Handler<S, Msg, R>) -> R
+    {
+        while let Some(msg) = shared.recv() {
+            if !handler.handle(msg, &shared) {
+                break;
+            }
+        }
+
+        handler.finish()
+    }
+}
+
+impl<Msg: Send, R> Drop for Looper<Msg, R> {
+    fn drop(&mut self) {
+        self.shared.close();
+        // Un panic in cleanup è già stato segnalato dal panic hook: propagarlo da Drop causerebbe
+        // un abort se il Looper viene distrutto durante un altro panic
+        if let Some(runner) = self.runner.take() {
+            let _ = runner.wait();
+        }
+    }
+}
+
+// Funzione di esempio per l'elaborazione dei messaggi
+fn process_message<Msg: Sync + Debug>(msg: Msg) {
+    println!("Processing Msg: {:?}", msg);
+}
+
+// Funzione di esempio per la pulizia
+fn cleanup() {
+    println!("Cleaning up...");
+}
+
+fn main() {
+    let looper = Looper::new(process_message, cleanup);
+
+    // Invia messaggi al looper
+    let _ = looper.send("Message 1");
+    let _ = looper.send("Message 2");
+
+    // Il looper sarà automaticamente pulito quando esce dall'ambito o viene richiamata std::mem::drop(looper):
+    // i messaggi ancora in coda vengono elaborati prima di cleanup
+    sleep(Duration::from_millis(200));
+    let _ = looper.send("Message 3");
+    looper.join();
+
+    // I messaggi ritardati vengono elaborati alla scadenza, quelli rimossi prima non lo sono mai
+    let looper = Looper::new(process_message, cleanup);
+    let _ = looper.send_delayed("Delayed", Duration::from_millis(100));
+    let _ = looper.send_delayed("Removed", Duration::from_millis(50));
+    let _ = looper.send_front("Urgent");
+    looper.remove_pending(|msg| *msg == "Removed");
+    sleep(Duration::from_millis(200));
+    looper.join();
+
+    // Lo stato del looper viene restituito da join
+    let counter = Looper::with_state(0, |count, _: &str| *count += 1, |count| count);
+    let _ = counter.send("Message 1");
+    let _ = counter.send("Message 2");
+    println!("Processed {} messages", counter.join());
+}
+#[cfg(test)]
+mod test {
+    use crate::{Looper, SendTimeoutError, SupervisionPolicy, TrySendError};
+    use soluzione_temi_malnati::future::{FutureError, Promise};
+    use std::sync::{mpsc, Arc, Mutex};
+    use std::thread::sleep;
+    use std::time::{Duration, Instant};
+
+    static PROCESSED: Mutex<Vec<u32>> = Mutex::new(vec![]);
+    static CLEANUPS: Mutex<usize> = Mutex::new(0);
+
+    fn process(msg: u32) {
+        // Elaborazione lenta, così al drop restano messaggi in coda
+        sleep(Duration::from_millis(5));
+        PROCESSED.lock().unwrap().push(msg);
+    }
+
+    fn cleanup() {
+        *CLEANUPS.lock().unwrap() += 1;
+    }
+
+    #[test]
+    fn survives_idle_and_drains_on_drop() {
+        let looper = Looper::new(process, cleanup);
+        looper.send(0).unwrap();
+        // Dopo un periodo di inattività il thread deve essere ancora attivo
+        sleep(Duration::from_millis(300));
+        for msg in 1..10 {
+            looper.send(msg).unwrap();
+        }
+        drop(looper);
+        assert_eq!(*PROCESSED.lock().unwrap(), (0..10).collect::<Vec<_>>());
+        assert_eq!(*CLEANUPS.lock().unwrap(), 1);
+    }
+
+    #[test]
+    fn closures_own_state() {
+        let log = Arc::new(Mutex::new(vec![]));
+        let (process_log, cleanup_log) = (Arc::clone(&log), Arc::clone(&log));
+        let mut count = 0;
+        let looper = Looper::new(move |msg: &str| {
+            count += 1;
+            process_log.lock().unwrap().push(format!("{} {}", count, msg));
+        }, move || cleanup_log.lock().unwrap().push("cleanup".to_string()));
+        looper.send("a").unwrap();
+        looper.send("b").unwrap();
+        looper.join();
+        assert_eq!(*log.lock().unwrap(), vec!["1 a", "2 b", "cleanup"]);
+    }
+
+    #[test]
+    fn join_returns_final_state() {
+        let looper = Looper::with_state(vec![], |seen: &mut Vec<u32>, msg| seen.push(msg), |seen| seen.iter().sum::<u32>());
+        for msg in 1..=4 {
+            looper.send(msg).unwrap();
+        }
+        assert_eq!(looper.join(), 10);
+    }
+
+    #[test]
+    fn delayed_messages_in_due_order() {
+        let start = Instant::now();
+        let looper = Looper::with_state(vec![], move |seen: &mut Vec<(&str, Duration)>, msg| seen.push((msg, start.elapsed())), |seen| seen);
+        looper.send_delayed("late", Duration::from_millis(80)).unwrap();
+        looper.send_at("soon", start + Duration::from_millis(40)).unwrap();
+        looper.send("now").unwrap();
+        sleep(Duration::from_millis(150));
+        let seen = looper.join();
+        assert_eq!(seen.iter().map(|(msg, _)| *msg).collect::<Vec<_>>(), vec!["now", "soon", "late"]);
+        assert!(seen[1].1 >= Duration::from_millis(40));
+        assert!(seen[2].1 >= Duration::from_millis(80));
+    }
+
+    #[test]
+    fn close_discards_delayed_messages_not_yet_due() {
+        let payload = Arc::new(());
+        let looper = Looper::with_state(vec![], |seen: &mut Vec<&str>, (msg, _): (&str, Arc<()>)| seen.push(msg), |seen| seen);
+        looper.send_delayed(("due", Arc::clone(&payload)), Duration::from_millis(10)).unwrap();
+        looper.send_delayed(("never", Arc::clone(&payload)), Duration::from_secs(10)).unwrap();
+        sleep(Duration::from_millis(30));
+        let start = Instant::now();
+        assert_eq!(looper.join(), vec!["due"]);
+        assert!(start.elapsed() < Duration::from_secs(1));
+        // Il messaggio scartato è stato distrutto
+        assert_eq!(Arc::strong_count(&payload), 1);
+    }
+
+    #[test]
+    fn front_and_remove_pending() {
+        let (unblock, blocked) = mpsc::channel();
+        let looper = Looper::with_state(vec![], move |seen: &mut Vec<&str>, msg| {
+            if msg == "block" {
+                blocked.recv().unwrap();
+            }
+            seen.push(msg);
+        }, |seen| seen);
+        looper.send("block").unwrap();
+        sleep(Duration::from_millis(20));
+        looper.send("a").unwrap();
+        looper.send("drop me").unwrap();
+        looper.send("b").unwrap();
+        looper.send_front("c").unwrap();
+        looper.send_delayed("drop me", Duration::from_millis(10)).unwrap();
+        looper.send_delayed("never", Duration::from_secs(10)).unwrap();
+        assert_eq!(looper.remove_pending(|msg| *msg == "drop me"), 2);
+        unblock.send(()).unwrap();
+        let start = Instant::now();
+        assert_eq!(looper.join(), vec!["block", "c", "a", "b"]);
+        assert!(start.elapsed() < Duration::from_secs(1));
+    }
+
+    // Somma i messaggi, lo zero manda in panic
+    fn supervised(policy: SupervisionPolicy) -> (Looper<u32, u32>, mpsc::Receiver<String>) {
+        let (errors, reports) = mpsc::channel();
+        let looper = Looper::supervised(|| 0, |sum: &mut u32, msg: u32| {
+            assert!(msg != 0, "bad message");
+            *sum += msg;
+        }, |sum| sum, policy, move |report| errors.send(report.message().unwrap().to_string()).unwrap());
+        (looper, reports)
+    }
+
+    #[test]
+    fn supervision_policies() {
+        for (policy, expected) in [(SupervisionPolicy::Skip, 10), (SupervisionPolicy::Restart, 7), (SupervisionPolicy::Stop, 3)] {
+            let (looper, reports) = supervised(policy);
+            for msg in [1, 2, 0, 3, 4] {
+                let _ = looper.send(msg);
+            }
+            assert_eq!(reports.recv().unwrap(), "bad message");
+            if policy == SupervisionPolicy::Stop {
+                sleep(Duration::from_millis(20));
+                assert!(looper.send(5).is_err());
+            }
+            assert_eq!(looper.join(), expected);
+        }
+    }
+
+    #[test]
+    fn request_returns_a_future() {
+        let looper = Looper::new(|(value, reply): (u32, Promise<u32>)| {
+            if value > 0 {
+                reply.set(value * 2);
+            }
+        }, || {});
+        assert_eq!(looper.request(|reply| (21, reply)).unwrap().get(), Ok(42));
+        // Un messaggio scartato senza risposta non blocca chi attende
+        assert_eq!(looper.request(|reply| (0, reply)).unwrap().get(), Err(FutureError::Disconnected));
+    }
+
+    #[test]
+    fn bounded_queue() {
+        let (unblock, blocked) = mpsc::channel();
+        let looper = Arc::new(Looper::bounded_with_state(2, vec![], move |seen: &mut Vec<u32>, msg| {
+            if msg == 1 {
+                blocked.recv().unwrap();
+            }
+            seen.push(msg);
+        }, |seen| seen));
+        looper.send(1).unwrap();
+        sleep(Duration::from_millis(20));
+        looper.send(2).unwrap();
+        looper.send(3).unwrap();
+        assert_eq!(looper.queue_len(), 2);
+        assert_eq!(looper.try_send(4), Err(TrySendError::Full(4)));
+        assert_eq!(looper.send_timeout(4, Duration::from_millis(20)), Err(SendTimeoutError::Timeout(4)));
+
+        // send attende che il thread del Looper estragga un messaggio
+        let producer = Arc::clone(&looper);
+        let handle = std::thread::spawn(move || producer.send(4).unwrap());
+        sleep(Duration::from_millis(20));
+        assert!(!handle.is_finished());
+        unblock.send(()).unwrap();
+        handle.join().unwrap();
+        let looper = Arc::into_inner(looper).unwrap();
+        assert_eq!(looper.join(), vec![1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn cleanup_panic_does_not_escape_drop() {
+        let looper = Looper::new(|_: u32| {}, || panic!("cleanup failed"));
+        let _ = looper.send(1);
+        drop(looper);
+    }
+}
@@ -0,0 +1,385 @@
+// Pool di thread per job indipendenti. Ogni worker ha una propria coda: i job creati da un worker
+// finiscono nella sua coda e vengono estratti dal fondo, mentre un worker senza lavoro prende i job
+// inviati dall'esterno e poi ruba dalla testa delle code degli altri worker.
+// Le code non usano MpMcChannel né MultiChannel: oltre a stare in altri binari, il primo richiede
+// elementi Clone ed ha capacità fissa (un worker che crea job si bloccherebbe sulla propria coda
+// piena), il secondo fa broadcast, e nessuno dei due permette di estrarre dal fondo o di rubare.
+// Allo stesso modo Scope non usa RankingBarrier, che attende un numero fisso di partecipanti,
+// mentre i job di uno scope aumentano ad ogni spawn. Il risultato di spawn è il Future della lib.
+use std::any::Any;
+use std::cell::Cell;
+use std::collections::VecDeque;
+use std::marker::PhantomData;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+use crate::{resume_failure, Stopped};
+use soluzione_temi_malnati::future::{Future, Promise};
+
+#[allow(dead_code)]
+type Job = Box<dyn FnOnce() + Send + 'static>;
+
+thread_local! {
+    // Pool e indice del worker eseguito dal thread corrente
+    static WORKER: Cell<Option<(usize, usize)>> = const { Cell::new(None) };
+}
+
+#[allow(dead_code)]
+struct PoolState {
+    // Job in coda e non ancora estratti da un worker
+    queued: usize,
+    shutdown: bool
+}
+
+#[allow(dead_code)]
+struct PoolShared {
+    injector: Mutex<VecDeque<Job>>,
+    deques: Vec<Mutex<VecDeque<Job>>>,
+    state: Mutex<PoolState>,
+    available: Condvar
+}
+
+#[allow(dead_code)]
+impl PoolShared {
+    fn id(&self) -> usize {
+        self as *const PoolShared as usize
+    }
+
+    fn current_worker(&self) -> Option<usize> {
+        WORKER.get().filter(|&(pool, _)| pool == self.id()).map(|(_, index)| index)
+    }
+
+    // Restituisce il job se il pool è stato chiuso
+    fn push(&self, job: Job) -> Result<(), Job> {
+        let mut state = self.state.lock().unwrap();
+        if state.shutdown {
+            return Err(job);
+        }
+        match self.current_worker() {
+            Some(index) => self.deques[index].lock().unwrap().push_back(job),
+            None => self.injector.lock().unwrap().push_back(job)
+        }
+        state.queued += 1;
+        self.available.notify_one();
+        Ok(())
+    }
+
+    fn find(&self, index: Option<usize>) -> Option<Job> {
+        let workers = self.deques.len();
+        let start = index.map_or(0, |index| index + 1);
+        let job = index.and_then(|index| self.deques[index].lock().unwrap().pop_back())
+            .or_else(|| self.injector.lock().unwrap().pop_front())
+            .or_else(|| (0..workers)
+                .map(|offset| (start + offset) % workers)
+                .filter(|&victim| Some(victim) != index)
+                .find_map(|victim| self.deques[victim].lock().unwrap().pop_front()));
+        if job.is_some() {
+            self.state.lock().unwrap().queued -= 1;
+        }
+        job
+    }
+
+    // Chi attende un job dal thread di un worker intanto esegue altri job, altrimenti un job in
+    // attesa nella sua coda non verrebbe mai eseguito
+    fn help(&self) -> Option<Job> {
+        self.current_worker().and_then(|index| self.find(Some(index)))
+    }
+
+    fn work(&self, index: usize) {
+        WORKER.set(Some((self.id(), index)));
+        loop {
+            if let Some(job) = self.find(Some(index)) {
+                // Un job che va in panic non deve fermare il worker
+                let _ = panic::catch_unwind(AssertUnwindSafe(job));
+                continue;
+            }
+            let state = self.state.lock().unwrap();
+            if state.queued > 0 {
+                continue;
+            }
+            if state.shutdown {
+                return;
+            }
+            drop(self.available.wait(state).unwrap());
+        }
+    }
+}
+
+// Risultato di un job avviato con ThreadPool::spawn
+#[allow(dead_code)]
+pub struct PoolHandle<T> {
+    result: Future<T>,
+    pool: Arc<PoolShared>
+}
+
+#[allow(dead_code)]
+impl<T> PoolHandle<T> {
+    pub fn is_finished(&self) -> bool {
+        self.result.is_done()
+    }
+
+    // Se il job è andato in panic il panic si propaga al chiamante
+    pub fn join(self) -> T {
+        while !self.result.is_done() {
+            match self.pool.help() {
+                Some(job) => {
+                    let _ = panic::catch_unwind(AssertUnwindSafe(job));
+                },
+                None => break
+            }
+        }
+        self.result.get().unwrap_or_else(|error| resume_failure(error))
+    }
+}
+
+// Job avviati da ThreadPool::scope, che possono prendere in prestito dati del chiamante
+#[allow(dead_code)]
+pub struct Scope<'scope, 'env: 'scope> {
+    pool: &'scope PoolShared,
+    pending: Mutex<usize>,
+    done: Condvar,
+    panic: Mutex<Option<Box<dyn Any + Send>>>,
+    _env: PhantomData<&'env mut &'env ()>
+}
+
+#[allow(dead_code)]
+impl<'scope> Scope<'scope, '_> {
+    pub fn spawn(&'scope self, f: impl FnOnce() + Send + 'scope) {
+        *self.pending.lock().unwrap() += 1;
+        let job: Box<dyn FnOnce() + Send + 'scope> = Box::new(move || {
+            if let Err(panic) = panic::catch_unwind(AssertUnwindSafe(f)) {
+                self.panic.lock().unwrap().get_or_insert(panic);
+            }
+            let mut pending = self.pending.lock().unwrap();
+            *pending -= 1;
+            if *pending == 0 {
+                self.done.notify_all();
+            }
+        });
+        // SAFETY: il job viene reso 'static ma usa self e i dati presi in prestito, che vivono solo
+        // per 'scope. È corretto perché ThreadPool::scope chiama sempre wait prima di ritornare,
+        // anche quando f va in panic, e wait ritorna solo quando pending torna a zero, cioè dopo che
+        // ogni job ha finito di eseguire. Un job inserito nel pool viene sempre eseguito: il pool
+        // non può essere distrutto durante scope, che lo prende in prestito, e shutdown svuota la
+        // coda prima di fermare i worker
+        let job: Job = unsafe { std::mem::transmute::<Box<dyn FnOnce() + Send + 'scope>, Job>(job) };
+        // A pool chiuso il job viene eseguito dal chiamante
+        if let Err(job) = self.pool.push(job) {
+            job();
+        }
+    }
+
+    fn wait(&self) {
+        loop {
+            if *self.pending.lock().unwrap() == 0 {
+                return;
+            }
+            match self.pool.help() {
+                Some(job) => {
+                    let _ = panic::catch_unwind(AssertUnwindSafe(job));
+                },
+                None => break
+            }
+        }
+        drop(self.done.wait_while(self.pending.lock().unwrap(), |pending| *pending > 0).unwrap());
+    }
+}
+
+#[allow(dead_code)]
+pub struct ThreadPool {
+    shared: Arc<PoolShared>,
+    workers: Mutex<Vec<thread::JoinHandle<()>>>
+}
+
+#[allow(dead_code)]
+impl ThreadPool {
+    pub fn new(threads: usize) -> ThreadPool {
+        assert!(threads > 0, "serve almeno un thread");
+        let shared = Arc::new(PoolShared {
+            injector: Mutex::new(VecDeque::new()),
+            deques: (0..threads).map(|_| Mutex::new(VecDeque::new())).collect(),
+            state: Mutex::new(PoolState { queued: 0, shutdown: false }),
+            available: Condvar::new()
+        });
+        let workers = (0..threads).map(|index| {
+            let shared = Arc::clone(&shared);
+            thread::spawn(move || shared.work(index))
+        }).collect();
+        ThreadPool {
+            shared,
+            workers: Mutex::new(workers)
+        }
+    }
+
+    pub fn execute(&self, f: impl FnOnce() + Send + 'static) -> Result<(), Box<dyn std::error::Error>> {
+        self.shared.push(Box::new(f)).map_err(|_| Box::new(Stopped) as Box<dyn std::error::Error>)
+    }
+
+    pub fn spawn<T: Send + 'static>(&self, f: impl FnOnce() -> T + Send + 'static) -> Result<PoolHandle<T>, Box<dyn std::error::Error>> {
+        let (promise, result) = Promise::new();
+        self.execute(move || {
+            promise.complete_with(f);
+        })?;
+        Ok(PoolHandle {
+            result,
+            pool: Arc::clone(&self.shared)
+        })
+    }
+
+    // Come spawn, ma il risultato è un Future: un panic del job diventa FutureError::Panicked e un
+    // job cancellato prima di partire non viene eseguito
+    pub fn submit<T: Send + 'static>(&self, f: impl FnOnce() -> T + Send + 'static) -> Result<Future<T>, Box<dyn std::error::Error>> {
+        let (promise, future) = Promise::new();
+        self.execute(move || {
+            if !promise.is_cancelled() {
+                promise.complete_with(f);
+            }
+        })?;
+        Ok(future)
+    }
+
+    // I job avviati con Scope::spawn possono usare dati presi in prestito: scope ritorna solo
+    // quando sono tutti terminati, e propaga il primo panic
+    pub fn scope<'env, T>(&self, f: impl for<'scope> FnOnce(&'scope Scope<'scope, 'env>) -> T) -> T {
+        let scope = Scope {
+            pool: &self.shared,
+            pending: Mutex::new(0),
+            done: Condvar::new(),
+            panic: Mutex::new(None),
+            _env: PhantomData
+        };
+        let result = panic::catch_unwind(AssertUnwindSafe(|| f(&scope)));
+        scope.wait();
+        if let Some(panic) = scope.panic.lock().unwrap().take() {
+            panic::resume_unwind(panic);
+        }
+        match result {
+            Ok(result) => result,
+            Err(panic) => panic::resume_unwind(panic)
+        }
+    }
+
+    // Rifiuta nuovi job, attende l'esecuzione di quelli già in coda e la terminazione dei worker.
+    // Non va chiamata da un job del pool
+    pub fn shutdown(&self) {
+        self.shared.state.lock().unwrap().shutdown = true;
+        self.shared.available.notify_all();
+        for worker in self.workers.lock().unwrap().drain(..) {
+            worker.join().unwrap();
+        }
+    }
+}
+
+impl Drop for ThreadPool {
+    fn drop(&mut self) {
+        self.shutdown();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::ThreadPool;
+    use soluzione_temi_malnati::future::{join_all, select_any, FutureError, Promise};
+    use std::sync::{mpsc, Arc, Mutex};
+    use std::thread::sleep;
+    use std::time::Duration;
+
+    #[test]
+    fn pool_execute_and_spawn() {
+        let pool = ThreadPool::new(4);
+        let (sender, receiver) = mpsc::channel();
+        for i in 0..10 {
+            let sender = sender.clone();
+            pool.execute(move || sender.send(i).unwrap()).unwrap();
+        }
+        let mut received: Vec<u32> = receiver.iter().take(10).collect();
+        received.sort();
+        assert_eq!(received, (0..10).collect::<Vec<_>>());
+
+        let handles: Vec<_> = (0..8u64).map(|i| pool.spawn(move || i * i).unwrap()).collect();
+        assert_eq!(handles.into_iter().map(|handle| handle.join()).sum::<u64>(), 140);
+
+        // Un job che va in panic non ferma il pool
+        let failed = pool.spawn(|| panic!("job failed")).unwrap();
+        assert!(std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| failed.join())).is_err());
+        assert_eq!(pool.spawn(|| 1).unwrap().join(), 1);
+    }
+
+    #[test]
+    fn pool_work_stealing() {
+        let pool = Arc::new(ThreadPool::new(4));
+        let threads = Arc::new(Mutex::new(std::collections::HashSet::new()));
+        let inner_pool = Arc::clone(&pool);
+        let inner_threads = Arc::clone(&threads);
+        // I job creati da un worker finiscono nella sua coda e gli altri worker li rubano
+        let total = pool.spawn(move || {
+            let handles: Vec<_> = (0..32u64).map(|i| {
+                let threads = Arc::clone(&inner_threads);
+                inner_pool.spawn(move || {
+                    threads.lock().unwrap().insert(std::thread::current().id());
+                    sleep(Duration::from_millis(5));
+                    i
+                }).unwrap()
+            }).collect();
+            handles.into_iter().map(|handle| handle.join()).sum::<u64>()
+        }).unwrap().join();
+        assert_eq!(total, (0..32).sum());
+        assert!(threads.lock().unwrap().len() > 1);
+    }
+
+    #[test]
+    fn pool_scope_borrows() {
+        let pool = ThreadPool::new(3);
+        let data: Vec<u64> = (1..=100).collect();
+        let mut sums = vec![0; 4];
+        pool.scope(|scope| {
+            for (chunk, sum) in data.chunks(25).zip(sums.iter_mut()) {
+                scope.spawn(move || *sum = chunk.iter().sum());
+            }
+        });
+        assert_eq!(sums, vec![325, 950, 1575, 2200]);
+    }
+
+    #[test]
+    fn pool_shutdown_drains_queue() {
+        let pool = ThreadPool::new(1);
+        let done = Arc::new(Mutex::new(0));
+        for _ in 0..5 {
+            let done = Arc::clone(&done);
+            pool.execute(move || {
+                sleep(Duration::from_millis(5));
+                *done.lock().unwrap() += 1;
+            }).unwrap();
+        }
+        pool.shutdown();
+        assert_eq!(*done.lock().unwrap(), 5);
+        assert!(pool.execute(|| {}).is_err());
+    }
+
+    #[test]
+    fn future_combinators_on_pool() {
+        let pool = ThreadPool::new(4);
+        let futures = (0..5u64).map(|i| pool.submit(move || {
+            sleep(Duration::from_millis(5 * (5 - i)));
+            i * 10
+        }).unwrap()).collect();
+        assert_eq!(join_all(futures).get(), Ok(vec![0, 10, 20, 30, 40]));
+
+        let failing = vec![pool.submit(|| 1).unwrap(), pool.submit(|| panic!("job failed")).unwrap()];
+        assert_eq!(join_all(failing).get(), Err(FutureError::Panicked("job failed".to_string())));
+
+        let slow = pool.submit(|| {
+            sleep(Duration::from_millis(200));
+            "slow"
+        }).unwrap();
+        let fast = pool.submit(|| "fast").unwrap();
+        assert_eq!(select_any(vec![slow, fast]).get(), Ok((1, "fast")));
+
+        let (first, second) = (Promise::<u32>::new(), Promise::<u32>::new());
+        let any = select_any(vec![first.1, second.1]);
+        first.0.fail(FutureError::Cancelled);
+        assert!(!any.is_done());
+        drop(second.0);
+        assert_eq!(any.get(), Err(FutureError::Disconnected));
+    }
+}
@@ -14,7 +14,8 @@
     le strutture quando il conteggio di weak arriva a 0.
 */
 
-use std::{fmt::Debug, rc::{Rc, Weak}, sync::{Arc, Condvar, Mutex}, thread::{sleep, spawn}, time::Duration};
+use std::{fmt::Debug, future::poll_fn, rc::{Rc, Weak}, sync::{Arc, Condvar, Mutex}, task::Poll, thread::{sleep, spawn}, time::Duration};
+use soluzione_temi_malnati::task::Wakers;
 
 fn _cyclic() {
     struct Node<T: Debug> {
@@ -186,6 +187,10 @@ impl<T: Clone> CircularBuffer<T> {
 struct MpMcChannel<E: Sync + Clone> {
     lock: Mutex<CircularBuffer<E>>,
     condvar: Condvar,
+    // Task in attesa nelle versioni async di send e recv, svegliati insieme ai thread sulla condvar.
+    // Un waker viene registrato mentre si possiede il lock del buffer, quindi una modifica
+    // successiva al controllo lo sveglia sempre
+    wakers: Mutex<Wakers>,
 }
 
 impl <E: Sync + Clone> MpMcChannel<E> {
@@ -194,9 +199,15 @@ impl <E: Sync + Clone> MpMcChannel<E> {
         MpMcChannel {
             lock: Mutex::new(CircularBuffer::new(n)),
             condvar: Condvar::new(),
+            wakers: Mutex::new(Wakers::new()),
         }
     }
 
+    fn notify(&self) {
+        self.condvar.notify_all();
+        self.wakers.lock().unwrap().wake_all();
+    }
+
     pub fn send(&self, el: E) -> Option<()> {
         let mut guard = self.condvar
         .wait_timeout_while(
@@ -208,6 +219,8 @@ impl <E: Sync + Clone> MpMcChannel<E> {
             return None;
         }
         if guard.0.put(el) {
+            drop(guard);
+            self.notify();
             Some(())
         } else {
             None
@@ -224,7 +237,10 @@ impl <E: Sync + Clone> MpMcChannel<E> {
         if guard.1.timed_out() {
             return None;
         }
-        guard.0.get()
+        let el = guard.0.get();
+        drop(guard);
+        self.notify();
+        el
     }
 
     pub fn shutdown(&self) -> Option<()> {
@@ -234,9 +250,51 @@ impl <E: Sync + Clone> MpMcChannel<E> {
          |buffer| !buffer.closed && buffer.len() == 0
         ).unwrap();
         guard.close();
+        drop(guard);
+        self.notify();
         Some(())
     }
-    
+
+    // A differenza di send non c'è timeout: si attende che ci sia spazio, None se il canale è chiuso
+    #[allow(dead_code)]
+    pub async fn send_async(&self, el: E) -> Option<()> {
+        let mut el = Some(el);
+        poll_fn(|cx| {
+            let mut buffer = self.lock.lock().unwrap();
+            if buffer.is_closed() {
+                return Poll::Ready(None);
+            }
+            if buffer.len() == buffer.n {
+                self.wakers.lock().unwrap().register(cx.waker());
+                return Poll::Pending;
+            }
+            buffer.put(el.take().unwrap());
+            drop(buffer);
+            self.notify();
+            Poll::Ready(Some(()))
+        }).await
+    }
+
+    // Attende un elemento, None se il canale è chiuso e vuoto
+    #[allow(dead_code)]
+    pub async fn recv_async(&self) -> Option<E> {
+        poll_fn(|cx| {
+            let mut buffer = self.lock.lock().unwrap();
+            match buffer.get() {
+                Some(el) => {
+                    drop(buffer);
+                    self.notify();
+                    Poll::Ready(Some(el))
+                },
+                None if buffer.is_closed() => Poll::Ready(None),
+                None => {
+                    self.wakers.lock().unwrap().register(cx.waker());
+                    Poll::Pending
+                }
+            }
+        }).await
+    }
+
 }
 
 pub fn main() {
@@ -272,4 +330,45 @@ pub fn main() {
     for handle in handles {
         let _ = handle.join();
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod test {
+    use crate::MpMcChannel;
+    use soluzione_temi_malnati::task::block_on;
+    use std::{sync::Arc, thread};
+
+    #[test]
+    fn async_send_waits_for_space() {
+        let channel = Arc::new(MpMcChannel::new(2));
+        let producer = {
+            let channel = Arc::clone(&channel);
+            thread::spawn(move || block_on(async {
+                for i in 0..10 {
+                    channel.send_async(i).await.unwrap();
+                }
+            }))
+        };
+        let mut received: Vec<usize> = block_on(async {
+            let mut received = vec![];
+            for _ in 0..10 {
+                received.push(channel.recv_async().await.unwrap());
+            }
+            received
+        });
+        producer.join().unwrap();
+        received.sort();
+        assert_eq!(received, (0..10).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn async_recv_ends_on_shutdown() {
+        let channel = MpMcChannel::new(2);
+        // shutdown attende che nel canale ci sia almeno un elemento
+        block_on(channel.send_async(1)).unwrap();
+        channel.shutdown();
+        assert_eq!(block_on(channel.send_async(2)), None);
+        assert_eq!(block_on(channel.recv_async()), Some(1));
+        assert_eq!(block_on(channel.recv_async()), None);
+    }
+}
@@ -4,7 +4,7 @@ I messaggi inviati a questo tipo di canale sono composti da singoli byte che ven
 tutti i ricevitori attualmente collegati.
 Metodi:
       new() -> Self // crea un nuovo canale senza alcun ricevitore collegato
-      subscribe(&self) -> mpsc::Receiver<u8> // collega un nuovo ricevitore al canale: da quando
+      subscribe(&self) -> Receiver              // collega un nuovo ricevitore al canale: da quando
                                              // questo metodo viene invocato, gli eventuali byte
                                              // inviati al canale saranno recapitati al ricevitore.
                                              // Se il ricevitore viene eliminato, il canale
@@ -16,12 +16,135 @@ Metodi:
                                             // se non c'è alcun sottoscrittore, notifica l'errore
 */
 
-use std::{sync::{mpsc::{channel, Receiver, SendError, Sender}, Arc, RwLock}, thread::{sleep, spawn}, time::Duration};
+use std::{collections::VecDeque, sync::{mpsc::SendError, Arc, Condvar, Mutex, RwLock}, thread::{sleep, spawn}, time::Duration};
 
 use rand::{thread_rng, Rng};
 
+// Cosa fare quando la coda limitata di un ricevitore è piena
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[allow(dead_code)]
+enum Overflow {
+    // send attende che il ricevitore legga almeno un byte
+    Block,
+    // Il nuovo byte viene scartato
+    DropNewest,
+    // Il byte più vecchio in coda viene scartato per fare spazio al nuovo
+    DropOldest,
+    // Il ricevitore viene scollegato: può leggere quanto già in coda e poi riceve Closed
+    Disconnect
+}
+
+#[derive(Debug, PartialEq)]
+enum RecvError {
+    // Il ricevitore era troppo lento e ha perso questo numero di byte
+    Lagged(u64),
+    // Il canale è stato distrutto o il ricevitore scollegato e la coda è vuota
+    Closed
+}
+
+struct QueueState {
+    data: VecDeque<u8>,
+    lagged: u64,
+    closed: bool
+}
+
+// Coda di un singolo ricevitore, condivisa tra MultiChannel e Receiver
+struct Queue {
+    state: Mutex<QueueState>,
+    not_empty: Condvar,
+    not_full: Condvar,
+    capacity: Option<usize>,
+    overflow: Overflow
+}
+
+impl Queue {
+    fn new(capacity: Option<usize>, overflow: Overflow) -> Queue {
+        Queue {
+            state: Mutex::new(QueueState {
+                data: VecDeque::new(),
+                lagged: 0,
+                closed: false
+            }),
+            not_empty: Condvar::new(),
+            not_full: Condvar::new(),
+            capacity,
+            overflow
+        }
+    }
+
+    // Restituisce false se il ricevitore è scollegato
+    fn push(&self, data: u8) -> bool {
+        let mut state = self.state.lock().unwrap();
+        if state.closed {
+            return false;
+        }
+        if let Some(capacity) = self.capacity.filter(|&capacity| state.data.len() >= capacity) {
+            match self.overflow {
+                Overflow::Block => {
+                    state = self.not_full.wait_while(state, |state| {
+                        !state.closed && state.data.len() >= capacity
+                    }).unwrap();
+                    if state.closed {
+                        return false;
+                    }
+                },
+                Overflow::DropNewest => {
+                    state.lagged += 1;
+                    self.not_empty.notify_one();
+                    return true;
+                },
+                Overflow::DropOldest => {
+                    state.data.pop_front();
+                    state.lagged += 1;
+                },
+                Overflow::Disconnect => {
+                    state.lagged += 1;
+                    state.closed = true;
+                    self.not_empty.notify_all();
+                    return false;
+                }
+            }
+        }
+        state.data.push_back(data);
+        self.not_empty.notify_one();
+        true
+    }
+
+    fn close(&self) {
+        self.state.lock().unwrap().closed = true;
+        self.not_empty.notify_all();
+        // Un send bloccato su questa coda piena deve poter proseguire
+        self.not_full.notify_all();
+    }
+}
+
+struct Receiver {
+    queue: Arc<Queue>
+}
+
+impl Receiver {
+    // Prima dei byte successivi segnala con Lagged quanti ne sono stati persi
+    pub fn recv(&self) -> Result<u8, RecvError> {
+        let mut state = self.queue.not_empty.wait_while(self.queue.state.lock().unwrap(), |state| {
+            state.data.is_empty() && state.lagged == 0 && !state.closed
+        }).unwrap();
+        if state.lagged > 0 {
+            return Err(RecvError::Lagged(std::mem::take(&mut state.lagged)));
+        }
+        let data = state.data.pop_front().ok_or(RecvError::Closed)?;
+        self.queue.not_full.notify_one();
+        Ok(data)
+    }
+}
+
+impl Drop for Receiver {
+    fn drop(&mut self) {
+        self.queue.close();
+    }
+}
+
 struct MultiChannel {
-    txs: RwLock<Vec<Sender<u8>>>
+    txs: RwLock<Vec<Arc<Queue>>>
 }
 
 impl MultiChannel {
@@ -32,29 +155,50 @@ impl MultiChannel {
         }
     }
 
-    pub fn subscribe(&self) -> Receiver<u8> {
-        let ( tx, rx ) = channel();
-        self.txs.write().unwrap().push(tx);
-        rx
+    pub fn subscribe(&self) -> Receiver {
+        self.connect(Queue::new(None, Overflow::Block))
+    }
+
+    // Il ricevitore conserva al massimo capacity byte non letti, overflow decide cosa fare dei
+    // successivi
+    #[allow(dead_code)]
+    pub fn subscribe_bounded(&self, capacity: usize, overflow: Overflow) -> Receiver {
+        self.connect(Queue::new(Some(capacity), overflow))
+    }
+
+    fn connect(&self, queue: Queue) -> Receiver {
+        let queue = Arc::new(queue);
+        self.txs.write().unwrap().push(Arc::clone(&queue));
+        Receiver { queue }
     }
 
     pub fn send(&self, data: u8) -> Result<(), SendError<u8>>{
-        let mut txs = self.txs.write().unwrap();
+        // Con Overflow::Block l'invio può attendere un ricevitore lento, quindi non si mantiene il
+        // lock sulla lista: nel frattempo altri possono collegarsi
+        let txs = self.txs.read().unwrap().clone();
 
-        if txs.len() == 0 {
-            return Err(SendError(0));
+        if txs.is_empty() {
+            return Err(SendError(data));
         }
 
-        let mut i = 0;
-        for tx in txs.clone() {
-            let res = tx.send(data);
-            if res.is_err() {
-                txs.remove(i);
-            }
-            i+=1;
+        let disconnected: Vec<_> = txs.iter().filter(|tx| !tx.push(data)).collect();
+        if !disconnected.is_empty() {
+            self.txs.write().unwrap().retain(|tx| !disconnected.iter().any(|closed| Arc::ptr_eq(tx, closed)));
         }
 
-        return Ok(());
+        // Tutti i ricevitori erano stati eliminati
+        if disconnected.len() == txs.len() {
+            return Err(SendError(data));
+        }
+        Ok(())
+    }
+}
+
+impl Drop for MultiChannel {
+    fn drop(&mut self) {
+        for tx in self.txs.get_mut().unwrap().iter() {
+            tx.close();
+        }
     }
 }
 
@@ -90,4 +234,75 @@ pub fn main() {
         println!("Closed connection with Receiver {}",i);
     } 
     let _ = handle.join();
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{MultiChannel, Overflow, RecvError};
+    use std::{sync::Arc, thread::{sleep, spawn}, time::Duration};
+
+    #[test]
+    fn send_reaches_all_receivers() {
+        let channel = MultiChannel::new();
+        assert!(channel.send(1).is_err());
+        let first = channel.subscribe();
+        let second = channel.subscribe();
+        channel.send(2).unwrap();
+        assert_eq!(first.recv(), Ok(2));
+        assert_eq!(second.recv(), Ok(2));
+        drop(first);
+        channel.send(3).unwrap();
+        drop(second);
+        assert!(channel.send(4).is_err());
+    }
+
+    #[test]
+    fn bounded_drop_newest_and_oldest() {
+        let channel = MultiChannel::new();
+        let newest = channel.subscribe_bounded(2, Overflow::DropNewest);
+        let oldest = channel.subscribe_bounded(2, Overflow::DropOldest);
+        for data in 0..5 {
+            channel.send(data).unwrap();
+        }
+        assert_eq!(newest.recv(), Err(RecvError::Lagged(3)));
+        assert_eq!(newest.recv(), Ok(0));
+        assert_eq!(newest.recv(), Ok(1));
+        assert_eq!(oldest.recv(), Err(RecvError::Lagged(3)));
+        assert_eq!(oldest.recv(), Ok(3));
+        assert_eq!(oldest.recv(), Ok(4));
+        drop(channel);
+        assert_eq!(newest.recv(), Err(RecvError::Closed));
+    }
+
+    #[test]
+    fn bounded_disconnect() {
+        let channel = MultiChannel::new();
+        let slow = channel.subscribe_bounded(1, Overflow::Disconnect);
+        let fast = channel.subscribe();
+        for data in 0..3 {
+            channel.send(data).unwrap();
+        }
+        assert_eq!(slow.recv(), Err(RecvError::Lagged(1)));
+        assert_eq!(slow.recv(), Ok(0));
+        assert_eq!(slow.recv(), Err(RecvError::Closed));
+        assert_eq!((0..3).map(|_| fast.recv().unwrap()).collect::<Vec<_>>(), vec![0, 1, 2]);
+    }
+
+    #[test]
+    fn bounded_block_waits_for_reader() {
+        let channel = Arc::new(MultiChannel::new());
+        let receiver = channel.subscribe_bounded(1, Overflow::Block);
+        let sender = {
+            let channel = Arc::clone(&channel);
+            spawn(move || {
+                for data in 0..4 {
+                    channel.send(data).unwrap();
+                }
+            })
+        };
+        sleep(Duration::from_millis(20));
+        assert!(!sender.is_finished());
+        assert_eq!((0..4).map(|_| receiver.recv().unwrap()).collect::<Vec<_>>(), vec![0, 1, 2, 3]);
+        sender.join().unwrap();
+    }
+}
@@ -77,41 +77,144 @@
 // Si implementi tale componente a scelta nei linguaggi C++ o Rust:
 use std::collections::HashMap;
 use std::fmt::Display;
-use std::sync::{Arc, Condvar, Mutex};
+use std::future::poll_fn;
+use std::sync::{Arc, Mutex};
 use std::hash::Hash;
+use std::task::Poll;
+use soluzione_temi_malnati::future::{Future, Promise};
+use soluzione_temi_malnati::task::Wakers;
+
+enum Slot<V> {
+    Ready(Arc<V>),
+    // Il valore è in calcolo in un altro thread, che completerà i Promise in attesa e sveglierà i
+    // task in attesa in get_async
+    Pending(Vec<Promise<Arc<V>>>, Wakers)
+}
+
+type Key<K, V> = (K, fn(K) -> V);
 
 pub struct ParallelCache<K, V> 
 where K: Eq + Hash + Clone, V: Display {
-    map: Mutex<(HashMap<(K, fn(K) -> V), Arc<V>>, bool)>,
-    condvar: Condvar,
+    map: Mutex<HashMap<Key<K, V>, Slot<V>>>
 }
 
-impl<K, V> ParallelCache<K, V> 
+// Toglie dalla cache il calcolo in corso se la funzione va in panic, così chi attende riceve un
+// errore e può riprovare
+struct Computing<'a, K, V>
+where K: Eq + Hash + Clone, V: Display {
+    cache: &'a ParallelCache<K, V>,
+    key: Option<Key<K, V>>
+}
+
+impl<K, V> Computing<'_, K, V>
 where K: Eq + Hash + Clone, V: Display {
+    fn finish(mut self, value: &Arc<V>) {
+        let key = self.key.take().unwrap();
+        let previous = self.cache.map.lock().unwrap().insert(key, Slot::Ready(Arc::clone(value)));
+        if let Some(Slot::Pending(waiters, mut wakers)) = previous {
+            for waiter in waiters {
+                waiter.set(Arc::clone(value));
+            }
+            wakers.wake_all();
+        }
+    }
+}
+
+impl<K, V> Drop for Computing<'_, K, V>
+where K: Eq + Hash + Clone, V: Display {
+    fn drop(&mut self) {
+        if let Some(key) = self.key.take() {
+            // I task in attesa riprovano, uno di loro ripeterà il calcolo
+            if let Some(Slot::Pending(_, mut wakers)) = self.cache.map.lock().unwrap().remove(&key) {
+                wakers.wake_all();
+            }
+        }
+    }
+}
+
+impl<K, V> Default for ParallelCache<K, V>
+where K: Eq + Hash + Clone, V: Display + Send + Sync + 'static {
+    fn default() -> Self {
+        ParallelCache::new()
+    }
+}
+
+impl<K, V> ParallelCache<K, V> 
+where K: Eq + Hash + Clone, V: Display + Send + Sync + 'static {
     pub fn new() -> ParallelCache<K, V> {
         ParallelCache {
-            map: Mutex::new((HashMap::new(), false)),
-            condvar: Condvar::new(),
+            map: Mutex::new(HashMap::new())
         }
     }
 
     pub fn get(&self, input: K, function: fn(K) -> V) -> Arc<V> {
+        loop {
+            // Un errore indica che il thread che calcolava il valore è andato in panic
+            if let Ok(value) = self.get_future(input.clone(), function).get() {
+                return value;
+            }
+        }
+    }
+
+    // Se il valore non è ancora stato calcolato la funzione viene invocata nel thread chiamante,
+    // mentre se è in calcolo in un altro thread il Future si completa quando quel calcolo termina.
+    // Il lock non viene mantenuto durante il calcolo, così chiavi diverse vengono calcolate in
+    // parallelo
+    pub fn get_future(&self, input: K, function: fn(K) -> V) -> Future<Arc<V>> {
+        let key = (input.clone(), function);
+        let mut map = self.map.lock().unwrap();
+        match map.get_mut(&key) {
+            Some(Slot::Ready(value)) => {
+                println!("Found already in cache: {}", value);
+                return Future::ready(Arc::clone(value));
+            },
+            Some(Slot::Pending(waiters, _)) => {
+                let (waiter, future) = Promise::new();
+                waiters.push(waiter);
+                return future;
+            },
+            None => {
+                map.insert(key.clone(), Slot::Pending(Vec::new(), Wakers::new()));
+            }
+        }
+        drop(map);
+
+        Future::ready(self.compute(key, input, function))
+    }
 
-        let mut guard = self.condvar.wait_while(self.map.lock().unwrap(), |map| {
-            !map.0.contains_key(&(input.clone(), function)) && map.1
-        }).unwrap();
-
-        let contains_key = guard.0.contains_key(&(input.clone(), function));
-        if !contains_key {
-            guard.1 = true;
-            guard.0.insert((input.clone(), function), Arc::new(function(input.clone())));
-            guard.1 = false;
-            self.condvar.notify_all();
-        } else {
-            println!("Found already in cache: {}", guard.0.get(&(input.clone(), function)).unwrap());
+    // Come get, ma chi trova il valore in calcolo in un altro thread o task sospende il task invece
+    // di bloccare il thread. Se il valore manca viene calcolato nel task chiamante
+    #[allow(dead_code)]
+    pub async fn get_async(&self, input: K, function: fn(K) -> V) -> Arc<V> {
+        let key = (input.clone(), function);
+        let found = poll_fn(|cx| {
+            let mut map = self.map.lock().unwrap();
+            match map.get_mut(&key) {
+                Some(Slot::Ready(value)) => {
+                    println!("Found already in cache: {}", value);
+                    Poll::Ready(Some(Arc::clone(value)))
+                },
+                Some(Slot::Pending(_, wakers)) => {
+                    wakers.register(cx.waker());
+                    Poll::Pending
+                },
+                None => {
+                    map.insert(key.clone(), Slot::Pending(Vec::new(), Wakers::new()));
+                    Poll::Ready(None)
+                }
+            }
+        }).await;
+        match found {
+            Some(value) => value,
+            None => self.compute(key, input, function)
         }
+    }
 
-        return Arc::clone(guard.0.get(&(input, function)).unwrap());
+    fn compute(&self, key: Key<K, V>, input: K, function: fn(K) -> V) -> Arc<V> {
+        let computing = Computing { cache: self, key: Some(key) };
+        let value = Arc::new(function(input));
+        computing.finish(&value);
+        value
     }
 }
 
@@ -120,7 +223,8 @@ pub fn main() {}
 #[cfg(test)]
 mod test {
     use crate::ParallelCache;
-    use std::{thread::spawn, sync::Arc};
+    use soluzione_temi_malnati::task::block_on;
+    use std::{future::Future, pin::pin, task::{Context, Waker}, thread::{sleep, spawn}, sync::{Arc, atomic::{AtomicBool, AtomicUsize, Ordering}}, time::Duration};
 
     #[test]
     fn single_threaded() {
@@ -153,4 +257,70 @@ mod test {
             handle.join().unwrap();
         }
     }
-}
\ No newline at end of file
+
+    static CALLS: AtomicUsize = AtomicUsize::new(0);
+
+    fn slow_square(input: u64) -> u64 {
+        CALLS.fetch_add(1, Ordering::SeqCst);
+        sleep(Duration::from_millis(50));
+        input * input
+    }
+
+    #[test]
+    fn computes_once_and_waiters_share_the_value() {
+        let parallel_cache = Arc::new(ParallelCache::new());
+        let handles: Vec<_> = (0..5).map(|_| {
+            let cache_clone = Arc::clone(&parallel_cache);
+            spawn(move || cache_clone.get(7, slow_square))
+        }).collect();
+        let values: Vec<_> = handles.into_iter().map(|handle| handle.join().unwrap()).collect();
+        assert!(values.iter().all(|value| Arc::ptr_eq(value, &values[0])));
+        assert_eq!(*values[0], 49);
+        assert_eq!(CALLS.load(Ordering::SeqCst), 1);
+        assert_eq!(parallel_cache.get_future(7, slow_square).get().map(|value| *value), Ok(49));
+    }
+
+    static FAILED: AtomicBool = AtomicBool::new(false);
+
+    // Va in panic solo la prima volta
+    fn flaky(input: u32) -> u32 {
+        if !FAILED.swap(true, Ordering::SeqCst) {
+            panic!("failed");
+        }
+        input + 1
+    }
+
+    #[test]
+    fn panicking_function_is_retried() {
+        let parallel_cache = Arc::new(ParallelCache::new());
+        let cache_clone = Arc::clone(&parallel_cache);
+        assert!(spawn(move || cache_clone.get(1, flaky)).join().is_err());
+        // Il calcolo fallito non resta in cache
+        assert_eq!(*parallel_cache.get(1, flaky), 2);
+    }
+
+    static ASYNC_CALLS: AtomicUsize = AtomicUsize::new(0);
+
+    fn slow_cube(input: u64) -> u64 {
+        ASYNC_CALLS.fetch_add(1, Ordering::SeqCst);
+        sleep(Duration::from_millis(50));
+        input * input * input
+    }
+
+    #[test]
+    fn async_waiters_do_not_block() {
+        let parallel_cache = Arc::new(ParallelCache::new());
+        let cache_clone = Arc::clone(&parallel_cache);
+        let computing = spawn(move || cache_clone.get(3, slow_cube));
+        sleep(Duration::from_millis(10));
+
+        // Il valore è in calcolo in un altro thread: il task registra il waker e restituisce il
+        // controllo
+        let mut get = pin!(parallel_cache.get_async(3, slow_cube));
+        assert!(get.as_mut().poll(&mut Context::from_waker(Waker::noop())).is_pending());
+        let value = block_on(get);
+        assert!(Arc::ptr_eq(&value, &computing.join().unwrap()));
+        assert_eq!(*block_on(parallel_cache.get_async(3, slow_cube)), 27);
+        assert_eq!(ASYNC_CALLS.load(Ordering::SeqCst), 1);
+    }
+}
@@ -0,0 +1,395 @@
+// Promise e Future: il risultato di un calcolo eseguito altrove, da attendere o da comporre con
+// altri calcoli.
+use std::fmt;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::Duration;
+use crate::panic_message;
+
+// Risultato di un calcolo che termina più tardi: il Promise lo produce, il Future lo consuma
+#[derive(Debug, PartialEq)]
+pub enum FutureError {
+    Cancelled,
+    // Il calcolo è andato in panic, contiene il messaggio del panic
+    Panicked(String),
+    // Il Promise è stato scartato senza un valore, oppure il valore è già stato letto
+    Disconnected,
+    Timeout
+}
+
+impl fmt::Display for FutureError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FutureError::Cancelled => write!(f, "the future was cancelled"),
+            FutureError::Panicked(message) => write!(f, "the computation panicked: {}", message),
+            FutureError::Disconnected => write!(f, "no value is available"),
+            FutureError::Timeout => write!(f, "no value before the timeout")
+        }
+    }
+}
+
+impl std::error::Error for FutureError {}
+
+type Continuation<T> = Box<dyn FnOnce(Result<T, FutureError>) + Send>;
+
+struct FutureState<T> {
+    result: Option<Result<T, FutureError>>,
+    // Impostata da then, riceve il risultato al posto di chi attende
+    continuation: Option<Continuation<T>>,
+    done: bool
+}
+
+struct FutureInner<T> {
+    state: Mutex<FutureState<T>>,
+    ready: Condvar
+}
+
+impl<T> FutureInner<T> {
+    // Il primo risultato vince, i successivi vengono ignorati
+    fn complete(&self, result: Result<T, FutureError>) -> bool {
+        let mut state = self.state.lock().unwrap();
+        if state.done {
+            return false;
+        }
+        state.done = true;
+        match state.continuation.take() {
+            Some(continuation) => {
+                drop(state);
+                continuation(result);
+            },
+            None => {
+                state.result = Some(result);
+                self.ready.notify_all();
+            }
+        }
+        true
+    }
+}
+
+pub struct Promise<T> {
+    inner: Arc<FutureInner<T>>
+}
+
+pub struct Future<T> {
+    inner: Arc<FutureInner<T>>
+}
+
+impl<T> Promise<T> {
+    pub fn new() -> (Promise<T>, Future<T>) {
+        let inner = Arc::new(FutureInner {
+            state: Mutex::new(FutureState { result: None, continuation: None, done: false }),
+            ready: Condvar::new()
+        });
+        (Promise { inner: Arc::clone(&inner) }, Future { inner })
+    }
+
+    // Restituisce false se il Future era già stato cancellato
+    pub fn set(self, value: T) -> bool {
+        self.inner.complete(Ok(value))
+    }
+
+    pub fn fail(self, error: FutureError) -> bool {
+        self.inner.complete(Err(error))
+    }
+
+    // Chi produce il valore può controllarlo per evitare lavoro inutile
+    pub fn is_cancelled(&self) -> bool {
+        self.inner.state.lock().unwrap().done
+    }
+
+    // Completa il Future con il risultato di f, oppure con Panicked se f va in panic. Restituisce
+    // false solo in caso di panic
+    pub fn complete_with(self, f: impl FnOnce() -> T) -> bool {
+        match panic::catch_unwind(AssertUnwindSafe(f)) {
+            Ok(value) => {
+                self.set(value);
+                true
+            },
+            Err(panic) => {
+                self.fail(FutureError::Panicked(panic_message(&*panic).unwrap_or_default().to_string()));
+                false
+            }
+        }
+    }
+}
+
+impl<T> Drop for Promise<T> {
+    fn drop(&mut self) {
+        self.inner.complete(Err(FutureError::Disconnected));
+    }
+}
+
+impl<T> Future<T> {
+    pub fn is_done(&self) -> bool {
+        self.inner.state.lock().unwrap().done
+    }
+
+    // Restituisce false se il risultato era già disponibile
+    pub fn cancel(&self) -> bool {
+        self.inner.complete(Err(FutureError::Cancelled))
+    }
+
+    pub fn get(self) -> Result<T, FutureError> {
+        let state = self.inner.ready.wait_while(self.inner.state.lock().unwrap(), |state| !state.done).unwrap();
+        Self::take(state)
+    }
+
+    // Allo scadere del timeout il Future resta utilizzabile
+    pub fn get_timeout(&self, timeout: Duration) -> Result<T, FutureError> {
+        let (state, wait) = self.inner.ready.wait_timeout_while(self.inner.state.lock().unwrap(), timeout, |state| !state.done).unwrap();
+        if wait.timed_out() {
+            return Err(FutureError::Timeout);
+        }
+        Self::take(state)
+    }
+
+    fn take(mut state: std::sync::MutexGuard<'_, FutureState<T>>) -> Result<T, FutureError> {
+        state.result.take().unwrap_or(Err(FutureError::Disconnected))
+    }
+}
+
+impl<T: Send + 'static> Future<T> {
+    // Future già completato
+    pub fn ready(value: T) -> Future<T> {
+        let (promise, future) = Promise::new();
+        promise.set(value);
+        future
+    }
+
+    // f riceve il risultato, anche se è un errore, nel thread che completa il Promise oppure
+    // subito se il risultato è già disponibile
+    pub fn then<U: Send + 'static>(self, f: impl FnOnce(Result<T, FutureError>) -> Result<U, FutureError> + Send + 'static) -> Future<U> {
+        let (promise, future) = Promise::new();
+        let continuation = move |result| {
+            match panic::catch_unwind(AssertUnwindSafe(|| f(result))) {
+                Ok(Ok(value)) => promise.set(value),
+                Ok(Err(error)) => promise.fail(error),
+                Err(panic) => promise.fail(FutureError::Panicked(panic_message(&*panic).unwrap_or_default().to_string()))
+            };
+        };
+        let mut state = self.inner.state.lock().unwrap();
+        if state.done {
+            let result = state.result.take().unwrap_or(Err(FutureError::Disconnected));
+            drop(state);
+            continuation(result);
+        } else {
+            state.continuation = Some(Box::new(continuation));
+        }
+        future
+    }
+
+    pub fn map<U: Send + 'static>(self, f: impl FnOnce(T) -> U + Send + 'static) -> Future<U> {
+        self.then(|result| result.map(f))
+    }
+}
+
+// Completato quando lo sono tutti i Future, con i valori nello stesso ordine, oppure al primo errore
+pub fn join_all<T: Send + 'static>(futures: Vec<Future<T>>) -> Future<Vec<T>> {
+    if futures.is_empty() {
+        return Future::ready(Vec::new());
+    }
+    let (promise, future) = Promise::new();
+    let pending = Arc::new(Mutex::new((Some(promise), futures.iter().map(|_| None).collect::<Vec<_>>(), futures.len())));
+    for (index, item) in futures.into_iter().enumerate() {
+        let pending = Arc::clone(&pending);
+        item.then(move |result| {
+            let mut pending = pending.lock().unwrap();
+            let (promise, values, remaining) = &mut *pending;
+            match result {
+                Ok(value) => {
+                    values[index] = Some(value);
+                    *remaining -= 1;
+                    if *remaining == 0 {
+                        if let Some(promise) = promise.take() {
+                            promise.set(values.iter_mut().map(|value| value.take().unwrap()).collect());
+                        }
+                    }
+                },
+                Err(error) => {
+                    if let Some(promise) = promise.take() {
+                        promise.fail(error);
+                    }
+                }
+            }
+            Ok(())
+        });
+    }
+    future
+}
+
+// Completato dal primo Future che produce un valore, insieme alla sua posizione. Se falliscono
+// tutti restituisce l'ultimo errore
+pub fn select_any<T: Send + 'static>(futures: Vec<Future<T>>) -> Future<(usize, T)> {
+    let (promise, future) = Promise::new();
+    let pending = Arc::new(Mutex::new((Some(promise), futures.len())));
+    for (index, item) in futures.into_iter().enumerate() {
+        let pending = Arc::clone(&pending);
+        item.then(move |result| {
+            let mut pending = pending.lock().unwrap();
+            let (promise, remaining) = &mut *pending;
+            *remaining -= 1;
+            match result {
+                Ok(value) => {
+                    if let Some(promise) = promise.take() {
+                        promise.set((index, value));
+                    }
+                },
+                Err(error) if *remaining == 0 => {
+                    if let Some(promise) = promise.take() {
+                        promise.fail(error);
+                    }
+                },
+                Err(_) => {}
+            }
+            Ok(())
+        });
+    }
+    future
+}
+
+#[cfg(test)]
+mod test {
+    use super::{join_all, select_any, Future, FutureError, Promise};
+    use std::thread::{sleep, spawn};
+    use std::time::Duration;
+
+    #[test]
+    fn promise_and_future() {
+        let (promise, future) = Promise::new();
+        assert!(!future.is_done());
+        assert_eq!(future.get_timeout(Duration::from_millis(10)), Err(FutureError::Timeout));
+        spawn(move || {
+            sleep(Duration::from_millis(10));
+            promise.set(21);
+        });
+        assert_eq!(future.map(|value| value * 2).get(), Ok(42));
+
+        // Un Promise scartato senza valore non blocca chi attende
+        let (promise, future) = Promise::<u32>::new();
+        drop(promise);
+        assert!(future.is_done());
+        assert_eq!(future.get(), Err(FutureError::Disconnected));
+
+        let (promise, future) = Promise::new();
+        assert!(future.cancel());
+        assert!(promise.is_cancelled());
+        assert!(!promise.set(1));
+        assert_eq!(future.get(), Err(FutureError::Cancelled));
+
+        let (promise, future) = Promise::<u32>::new();
+        let recovered = future.then(|result| match result {
+            Err(FutureError::Panicked(message)) => Ok(message),
+            _ => Ok(String::new())
+        });
+        promise.fail(FutureError::Panicked("boom".to_string()));
+        assert_eq!(recovered.get(), Ok("boom".to_string()));
+
+        let (promise, future) = Promise::<u32>::new();
+        assert!(!promise.complete_with(|| panic!("failed")));
+        assert_eq!(future.get(), Err(FutureError::Panicked("failed".to_string())));
+    }
+
+    #[test]
+    fn then_runs_whether_or_not_the_result_is_ready() {
+        // Risultato già disponibile: f viene eseguita subito
+        let future = Future::ready(1).then(|result| result.map(|value| value + 1));
+        assert!(future.is_done());
+        assert_eq!(future.get(), Ok(2));
+
+        // Risultato non ancora disponibile: f viene eseguita da chi completa il Promise
+        let (promise, future) = Promise::new();
+        let chained = future.then(|result: Result<u32, FutureError>| result.map(|value| value * 3));
+        assert!(!chained.is_done());
+        spawn(move || promise.set(5)).join().unwrap();
+        assert_eq!(chained.get(), Ok(15));
+
+        // Un panic in f completa il nuovo Future con Panicked
+        let panicked = Future::ready(1).then(|_| -> Result<u32, FutureError> { panic!("then") });
+        assert_eq!(panicked.get(), Err(FutureError::Panicked("then".to_string())));
+    }
+
+    #[test]
+    fn map_propagates_errors_without_calling_f() {
+        let (promise, future) = Promise::<u32>::new();
+        let mapped = future.map(|_| -> u32 { unreachable!() });
+        promise.fail(FutureError::Timeout);
+        assert_eq!(mapped.get(), Err(FutureError::Timeout));
+
+        assert_eq!(Future::ready("a").map(str::len).map(|len| len * 10).get(), Ok(10));
+        assert_eq!(Future::ready(1).map(|_| -> u32 { panic!("map") }).get(), Err(FutureError::Panicked("map".to_string())));
+    }
+
+    #[test]
+    fn join_all_keeps_the_order_and_stops_at_the_first_error() {
+        let (promises, futures): (Vec<_>, Vec<_>) = (0..3).map(|_| Promise::new()).unzip();
+        let joined = join_all(futures);
+        // Completati in ordine inverso, i valori restano nell'ordine dei Future
+        for (index, promise) in promises.into_iter().enumerate().rev() {
+            assert!(!joined.is_done());
+            promise.set(index);
+        }
+        assert_eq!(joined.get(), Ok(vec![0, 1, 2]));
+
+        assert_eq!(join_all(Vec::<Future<u32>>::new()).get(), Ok(vec![]));
+
+        let (first, pending) = Promise::<u32>::new();
+        let (_second, never) = Promise::new();
+        let joined = join_all(vec![pending, never]);
+        first.fail(FutureError::Cancelled);
+        assert_eq!(joined.get_timeout(Duration::from_secs(1)), Err(FutureError::Cancelled));
+    }
+
+    #[test]
+    fn select_any_returns_the_first_value_or_the_last_error() {
+        let (slow, slow_future) = Promise::new();
+        let (fast, fast_future) = Promise::new();
+        let selected = select_any(vec![slow_future, fast_future]);
+        fast.set("fast");
+        slow.set("slow");
+        assert_eq!(selected.get(), Ok((1, "fast")));
+
+        // Gli errori vengono ignorati finché resta un Future che può produrre un valore
+        let (failing, failing_future) = Promise::new();
+        let (working, working_future) = Promise::new();
+        let selected = select_any(vec![failing_future, working_future]);
+        failing.fail(FutureError::Cancelled);
+        assert!(!selected.is_done());
+        working.set(7);
+        assert_eq!(selected.get(), Ok((1, 7)));
+
+        let (first, first_future) = Promise::<u32>::new();
+        let (second, second_future) = Promise::<u32>::new();
+        let selected = select_any(vec![first_future, second_future]);
+        first.fail(FutureError::Cancelled);
+        second.fail(FutureError::Timeout);
+        assert_eq!(selected.get(), Err(FutureError::Timeout));
+    }
+
+    #[test]
+    fn dropped_promise_completes_the_combinators() {
+        let (promise, future) = Promise::<u32>::new();
+        let mapped = future.map(|value| value + 1);
+        drop(promise);
+        assert_eq!(mapped.get(), Err(FutureError::Disconnected));
+
+        let (dropped, dropped_future) = Promise::<u32>::new();
+        let (kept, kept_future) = Promise::new();
+        let joined = join_all(vec![kept_future, dropped_future]);
+        drop(dropped);
+        assert_eq!(joined.get_timeout(Duration::from_secs(1)), Err(FutureError::Disconnected));
+        drop(kept);
+
+        let (dropped, dropped_future) = Promise::<u32>::new();
+        let (kept, kept_future) = Promise::new();
+        let selected = select_any(vec![dropped_future, kept_future]);
+        drop(dropped);
+        assert!(!selected.is_done());
+        kept.set(3);
+        assert_eq!(selected.get(), Ok((1, 3)));
+
+        let (dropped, dropped_future) = Promise::<u32>::new();
+        let selected = select_any(vec![dropped_future]);
+        spawn(move || drop(dropped)).join().unwrap();
+        assert_eq!(selected.get(), Err(FutureError::Disconnected));
+    }
+}
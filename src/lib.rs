@@ -0,0 +1,11 @@
+// Tipi condivisi dalle soluzioni in src/bin.
+use std::any::Any;
+
+pub mod future;
+pub mod task;
+
+// Il messaggio passato a panic!, se è una stringa
+pub fn panic_message(payload: &(dyn Any + Send)) -> Option<&str> {
+    payload.downcast_ref::<&str>().copied()
+        .or_else(|| payload.downcast_ref::<String>().map(String::as_str))
+}
@@ -0,0 +1,240 @@
+// Supporto minimo per le versioni async delle primitive, senza un runtime esterno: block_on
+// esegue un future nel thread corrente, Wakers raccoglie i task in attesa di una condizione e
+// Deadline sveglia un task ad un istante tramite un unico thread timer condiviso.
+use std::collections::BTreeMap;
+use std::future::{ poll_fn, Future };
+use std::pin::pin;
+use std::sync::atomic::{ AtomicU64, Ordering };
+use std::sync::{ Arc, Condvar, Mutex, Once };
+use std::task::{ Context, Poll, Wake, Waker };
+use std::thread::{ self, Thread };
+use std::time::{ Duration, Instant };
+
+// Il thread si sospende finché il waker non lo risveglia
+struct ThreadWaker(Thread);
+
+impl Wake for ThreadWaker {
+    fn wake(self: Arc<Self>) {
+        self.0.unpark();
+    }
+}
+
+pub fn block_on<F: Future>(future: F) -> F::Output {
+    let mut future = pin!(future);
+    let waker = Waker::from(Arc::new(ThreadWaker(thread::current())));
+    let mut cx = Context::from_waker(&waker);
+    loop {
+        if let Poll::Ready(output) = future.as_mut().poll(&mut cx) {
+            return output;
+        }
+        thread::park();
+    }
+}
+
+// Task in attesa di una condizione. Un task che si registra più volte, per esempio perché
+// risvegliato senza che la condizione sia cambiata, occupa un solo posto
+#[derive(Default)]
+pub struct Wakers(Vec<Waker>);
+
+impl Wakers {
+    pub fn new() -> Wakers {
+        Wakers(Vec::new())
+    }
+
+    pub fn register(&mut self, waker: &Waker) {
+        if !self.0.iter().any(|registered| registered.will_wake(waker)) {
+            self.0.push(waker.clone());
+        }
+    }
+
+    pub fn wake_all(&mut self) {
+        for waker in self.0.drain(..) {
+            waker.wake();
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+struct Timer {
+    // Ordinati per scadenza, l'id distingue le registrazioni con la stessa scadenza
+    entries: Mutex<BTreeMap<(Instant, u64), Waker>>,
+    next_id: AtomicU64,
+    changed: Condvar
+}
+
+static TIMER: Timer = Timer {
+    entries: Mutex::new(BTreeMap::new()),
+    next_id: AtomicU64::new(0),
+    changed: Condvar::new()
+};
+
+static TIMER_THREAD: Once = Once::new();
+
+impl Timer {
+    fn register(&self, at: Instant, waker: Waker) -> (Instant, u64) {
+        TIMER_THREAD.call_once(|| {
+            thread::spawn(|| TIMER.run());
+        });
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let mut entries = self.entries.lock().unwrap();
+        // Il thread deve ricalcolare l'attesa solo se la nuova scadenza è la più vicina
+        let earliest = entries.keys().next().is_none_or(|&(first, _)| at < first);
+        entries.insert((at, id), waker);
+        if earliest {
+            self.changed.notify_one();
+        }
+        (at, id)
+    }
+
+    // Se la scadenza è già passata la voce è stata rimossa dal thread e non resta nulla da fare
+    fn cancel(&self, key: (Instant, u64)) {
+        self.entries.lock().unwrap().remove(&key);
+    }
+
+    fn run(&self) {
+        let mut entries = self.entries.lock().unwrap();
+        loop {
+            let now = Instant::now();
+            let pending = match entries.keys().find(|&&(at, _)| at > now) {
+                Some(&first) => entries.split_off(&first),
+                None => BTreeMap::new()
+            };
+            let due = std::mem::replace(&mut *entries, pending);
+            if !due.is_empty() {
+                // Un waker potrebbe registrare una nuova scadenza
+                drop(entries);
+                for waker in due.into_values() {
+                    waker.wake();
+                }
+                entries = self.entries.lock().unwrap();
+                continue;
+            }
+            entries = match entries.keys().next() {
+                Some(&(at, _)) => self.changed.wait_timeout(entries, at.saturating_duration_since(now)).unwrap().0,
+                None => self.changed.wait(entries).unwrap()
+            };
+        }
+    }
+}
+
+// Sveglia di un future presso il timer condiviso. Viene registrata di nuovo solo se cambiano la
+// scadenza o il waker, così un future interrogato più volte non accumula registrazioni; la
+// registrazione precedente e quella ancora attiva al drop vengono rimosse dal timer
+#[derive(Default)]
+pub struct Deadline(Option<((Instant, u64), Waker)>);
+
+impl Deadline {
+    pub fn new() -> Deadline {
+        Deadline(None)
+    }
+
+    pub fn wake_at(&mut self, at: Instant, waker: &Waker) {
+        if self.0.as_ref().is_some_and(|((armed, _), armed_waker)| *armed == at && armed_waker.will_wake(waker)) {
+            return;
+        }
+        if let Some((key, _)) = self.0.take() {
+            TIMER.cancel(key);
+        }
+        self.0 = Some((TIMER.register(at, waker.clone()), waker.clone()));
+    }
+}
+
+impl Drop for Deadline {
+    fn drop(&mut self) {
+        if let Some((key, _)) = self.0.take() {
+            TIMER.cancel(key);
+        }
+    }
+}
+
+// Come thread::sleep, ma sospende il task invece del thread
+pub async fn sleep(duration: Duration) {
+    let at = Instant::now() + duration;
+    let mut deadline = Deadline::new();
+    poll_fn(|cx| {
+        if Instant::now() >= at {
+            return Poll::Ready(());
+        }
+        deadline.wake_at(at, cx.waker());
+        Poll::Pending
+    }).await
+}
+
+#[cfg(test)]
+mod test {
+    use super::{ block_on, sleep, Deadline, Wakers, TIMER };
+    use std::sync::Arc;
+    use std::sync::atomic::{ AtomicUsize, Ordering };
+    use std::task::{ Wake, Waker };
+    use std::thread;
+    use std::time::{ Duration, Instant };
+
+    struct CountingWaker(AtomicUsize);
+
+    impl Wake for CountingWaker {
+        fn wake(self: Arc<Self>) {
+            self.0.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    #[test]
+    fn wakers_are_registered_once() {
+        let counter = Arc::new(CountingWaker(AtomicUsize::new(0)));
+        let waker = Waker::from(Arc::clone(&counter));
+        let mut wakers = Wakers::new();
+        for _ in 0..10 {
+            wakers.register(&waker);
+        }
+        wakers.register(Waker::noop());
+        assert_eq!(wakers.len(), 2);
+        wakers.wake_all();
+        assert!(wakers.is_empty());
+        assert_eq!(counter.0.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn sleep_uses_the_shared_timer() {
+        let start = Instant::now();
+        let sleepers: Vec<_> = (1..=5u64).map(|i| thread::spawn(move || block_on(sleep(Duration::from_millis(10 * i))))).collect();
+        for sleeper in sleepers {
+            sleeper.join().unwrap();
+        }
+        assert!(start.elapsed() >= Duration::from_millis(50));
+
+        // Interrogare di nuovo il future non registra una nuova scadenza
+        let counter = Arc::new(CountingWaker(AtomicUsize::new(0)));
+        let waker = Waker::from(Arc::clone(&counter));
+        let mut deadline = Deadline::new();
+        let at = Instant::now() + Duration::from_millis(20);
+        for _ in 0..10 {
+            deadline.wake_at(at, &waker);
+        }
+        thread::sleep(Duration::from_millis(80));
+        assert_eq!(counter.0.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn dropped_deadline_leaves_the_timer() {
+        let counter = Arc::new(CountingWaker(AtomicUsize::new(0)));
+        let waker = Waker::from(Arc::clone(&counter));
+        let mut deadline = Deadline::new();
+        deadline.wake_at(Instant::now() + Duration::from_millis(30), &waker);
+        let first = deadline.0.as_ref().unwrap().0;
+        // Spostare la scadenza rimuove quella precedente
+        deadline.wake_at(Instant::now() + Duration::from_millis(40), &waker);
+        let second = deadline.0.as_ref().unwrap().0;
+        assert!(!TIMER.entries.lock().unwrap().contains_key(&first));
+        assert!(TIMER.entries.lock().unwrap().contains_key(&second));
+        drop(deadline);
+        assert!(!TIMER.entries.lock().unwrap().contains_key(&second));
+        thread::sleep(Duration::from_millis(80));
+        assert_eq!(counter.0.load(Ordering::SeqCst), 0);
+    }
+}